@@ -0,0 +1,209 @@
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+// What happens to a window once its command exits
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ExitPolicy {
+    Close,
+    // Keeps the window and its content around until a key is pressed in it
+    Hold,
+    // Runs the command again in the same window
+    Restart,
+}
+
+impl FromStr for ExitPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ExitPolicy, ()> {
+        match s {
+            "close" => Ok(ExitPolicy::Close),
+            "hold" => Ok(ExitPolicy::Hold),
+            "restart" => Ok(ExitPolicy::Restart),
+            _ => Err(())
+        }
+    }
+}
+
+// Which windows let their programs read the clipboard through OSC 52, written like "true", "false" or a list of
+// window ids like "1,3"
+#[derive(Clone, PartialEq, Debug)]
+pub enum ClipboardRead {
+    All,
+    Windows(Vec<usize>),
+}
+
+impl ClipboardRead {
+    pub fn allows(&self, window_id: usize) -> bool {
+        match self {
+            ClipboardRead::All => true,
+            ClipboardRead::Windows(ids) => ids.contains(&window_id)
+        }
+    }
+}
+
+impl FromStr for ClipboardRead {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<ClipboardRead, ()> {
+        match s {
+            "true" => Ok(ClipboardRead::All),
+            "false" => Ok(ClipboardRead::Windows(vec![])),
+            _ => s.split(',')
+                .map(|id| id.trim().parse().map_err(|_| ()))
+                .collect::<Result<_, _>>()
+                .map(ClipboardRead::Windows)
+        }
+    }
+}
+
+// A key TermUI handles itself instead of passing it to the focused window, written like "ctrl-q" or "alt-shift-f5"
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub fn matches(&self, event: &KeyEvent) -> bool {
+        event.code == self.code && event.modifiers == self.modifiers
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<KeyBinding, ()> {
+        let mut parts: Vec<&str> = s.split('-').collect();
+        let key = parts.pop().ok_or(())?;
+        let mut modifiers = KeyModifiers::empty();
+        for part in parts {
+            modifiers |= match part {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(())
+            };
+        }
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(ch), None) => KeyCode::Char(ch),
+            _ => match key.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n) => KeyCode::F(n),
+                None => return Err(())
+            }
+        };
+        Ok(KeyBinding {
+            code,
+            modifiers,
+        })
+    }
+}
+
+pub struct Config {
+    // Upper bound on how many frames get drawn per second, output arriving faster is coalesced
+    pub max_frame_rate: u32,
+    // Lines kept per window once they scroll off the top, 0 disables the scrollback
+    pub scrollback_lines: usize,
+    // What windows set TERM to, terminfo/termui.terminfo describes exactly what they support
+    pub term: String,
+    // The policy windows start with
+    pub exit_policy: ExitPolicy,
+    pub quit_key: KeyBinding,
+    // Writes the clipboard into the focused window
+    pub paste_key: KeyBinding,
+    // Whether what programs copy through OSC 52 also goes to the host terminal's clipboard
+    pub clipboard_to_host: bool,
+    // The windows whose programs may read the clipboard through OSC 52
+    pub clipboard_read: ClipboardRead,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_frame_rate: 60,
+            scrollback_lines: 1000,
+            term: "xterm-256color".to_string(),
+            exit_policy: ExitPolicy::Close,
+            quit_key: KeyBinding {
+                code: KeyCode::Char('q'),
+                modifiers: KeyModifiers::CONTROL,
+            },
+            paste_key: KeyBinding {
+                code: KeyCode::Char('v'),
+                modifiers: KeyModifiers::ALT,
+            },
+            clipboard_to_host: true,
+            clipboard_read: ClipboardRead::Windows(vec![]),
+        }
+    }
+}
+
+fn env_value<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok()?.parse().ok()
+}
+
+impl Config {
+    // Every setting can be overridden through a TERMUI_ environment variable
+    pub fn from_env() -> Config {
+        let mut config = Config::default();
+        if let Some(max_frame_rate) = env_value("TERMUI_MAX_FRAME_RATE") {
+            config.max_frame_rate = max_frame_rate;
+        }
+        if let Some(scrollback_lines) = env_value("TERMUI_SCROLLBACK_LINES") {
+            config.scrollback_lines = scrollback_lines;
+        }
+        if let Some(term) = env_value("TERMUI_TERM") {
+            config.term = term;
+        }
+        if let Some(exit_policy) = env_value("TERMUI_EXIT_POLICY") {
+            config.exit_policy = exit_policy;
+        }
+        if let Some(quit_key) = env_value("TERMUI_QUIT_KEY") {
+            config.quit_key = quit_key;
+        }
+        if let Some(paste_key) = env_value("TERMUI_PASTE_KEY") {
+            config.paste_key = paste_key;
+        }
+        if let Some(clipboard_to_host) = env_value("TERMUI_CLIPBOARD_TO_HOST") {
+            config.clipboard_to_host = clipboard_to_host;
+        }
+        if let Some(clipboard_read) = env_value("TERMUI_CLIPBOARD_READ") {
+            config.clipboard_read = clipboard_read;
+        }
+        config
+    }
+
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.max_frame_rate.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use super::{ClipboardRead, KeyBinding};
+
+    #[test]
+    fn clipboard_reads_per_window() {
+        assert_eq!("true".parse(), Ok(ClipboardRead::All));
+        assert_eq!("false".parse(), Ok(ClipboardRead::Windows(vec![])));
+        let read: ClipboardRead = "1, 3".parse().unwrap();
+        assert!(read.allows(1) && !read.allows(2) && read.allows(3));
+        assert_eq!("1,x".parse::<ClipboardRead>(), Err(()));
+    }
+
+    #[test]
+    fn key_bindings() {
+        let binding: KeyBinding = "ctrl-q".parse().unwrap();
+        assert_eq!((binding.code, binding.modifiers), (KeyCode::Char('q'), KeyModifiers::CONTROL));
+        let binding: KeyBinding = "alt-shift-f5".parse().unwrap();
+        assert_eq!((binding.code, binding.modifiers), (KeyCode::F(5), KeyModifiers::ALT | KeyModifiers::SHIFT));
+        assert!(binding.matches(&KeyEvent::new(KeyCode::F(5), KeyModifiers::ALT | KeyModifiers::SHIFT)));
+        assert!(!binding.matches(&KeyEvent::new(KeyCode::F(5), KeyModifiers::ALT)));
+        assert_eq!("super-q".parse::<KeyBinding>(), Err(()));
+        assert_eq!("ctrl-esc".parse::<KeyBinding>(), Err(()));
+        assert_eq!("".parse::<KeyBinding>(), Err(()));
+    }
+}
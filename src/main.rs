@@ -1,36 +1,134 @@
+mod config;
 mod renderer;
 mod screen;
 
-use crossterm::{event::*, Result, terminal::size, QueueableCommand};
-use crate::screen::{Screen, SimpleTerminalWindow};
-use crossterm::cursor::{DisableBlinking, EnableBlinking};
-use std::io::{Write, stdout, Read};
-use std::process::{exit, Command};
-use crossterm::terminal::{enable_raw_mode, disable_raw_mode};
-use std::ops::Deref;
-use std::borrow::Borrow;
+use crossterm::{event::*, terminal::size, QueueableCommand};
+use crate::config::Config;
+use crate::screen::{clipboard, Command, Screen, SimpleTerminalWindow};
+use crate::renderer::Renderer;
+use crossbeam::channel::{bounded, select, unbounded, Receiver, RecvError};
+use crossterm::cursor::{DisableBlinking, EnableBlinking, Hide, Show};
+use crossterm::style::{Attribute, SetAttribute};
+use std::io::Write;
+use crossterm::terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::thread::sleep;
-use std::time::Duration;
-use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// crossterm's read blocks, so terminal events are forwarded from their own thread
+fn spawn_input_thread() -> Receiver<Event> {
+    let (sender, receiver) = unbounded();
+    thread::Builder::new()
+        .name("input".to_string())
+        .spawn(move || {
+            while let Ok(event) = read() {
+                if sender.send(event).is_err() {
+                    return;
+                }
+            }
+        })
+        .unwrap();
+    receiver
+}
+
+// Blocks until there is terminal input, output from a window, or the timeout expires.
+// Fails once the input thread stopped, since no more input can come
+fn wait_for_event(input: &Receiver<Event>, wakeup: &Receiver<()>, timeout: Option<Duration>) -> Result<Option<Event>, RecvError> {
+    match timeout {
+        Some(timeout) => select! {
+            recv(input) -> event => event.map(Some),
+            recv(wakeup) -> _ => Ok(None),
+            default(timeout) => Ok(None),
+        },
+        None => select! {
+            recv(input) -> event => event.map(Some),
+            recv(wakeup) -> _ => Ok(None),
+        }
+    }
+}
+
+// Windows run the command given on the command line, or the user's shell
+fn window_command() -> Command {
+    let mut args = std::env::args().skip(1);
+    match args.next() {
+        Some(program) => Command::new(&program).args(args),
+        None => Command::login_shell()
+    }
+}
+
+// How long children get to exit after being hung up, and then after being killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Undoes the setup done at startup, errors are ignored since this also runs while panicking
+fn restore_terminal() {
+    let mut stdout = std::io::stdout();
+    let _ = stdout.queue(SetAttribute(Attribute::Reset));
+    let _ = stdout.queue(LeaveAlternateScreen);
+    let _ = stdout.queue(EnableBlinking);
+    let _ = stdout.queue(Show);
+    let _ = stdout.queue(DisableMouseCapture);
+    let _ = stdout.queue(DisableBracketedPaste);
+    let _ = stdout.flush();
+    let _ = disable_raw_mode();
+}
+
+// Waits until every container is gone or the timeout expires
+fn wait_for_containers(screen: &mut Screen, wakeup: &Receiver<()>, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        screen.update_content();
+        let now = Instant::now();
+        if screen.containers.is_empty() || now >= deadline {
+            return;
+        }
+        let _ = wakeup.recv_timeout(deadline - now);
+    }
+}
+
+// Hangs up every window's command and waits for them to be reaped, killing the ones that don't exit in time
+fn shut_down(screen: &mut Screen, wakeup: &Receiver<()>) {
+    for force in [false, true].iter() {
+        for con in screen.containers.iter() {
+            con.borrow_mut().shut_down(*force);
+        }
+        wait_for_containers(screen, wakeup, SHUTDOWN_TIMEOUT);
+    }
+}
 
 fn main() {
+    let config = Config::from_env();
+    let command = window_command();
     let mut stdout = std::io::stdout();
     let mut screen = Screen::new();
 
-    enable_raw_mode();
+    // Leave the host terminal usable whatever happens, the panic message would be unreadable in raw mode
+    let default_panic_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_panic_hook(info);
+    }));
+
+    enable_raw_mode().unwrap();
     stdout
+        .queue(EnterAlternateScreen).unwrap()
         .queue(DisableBlinking).unwrap()
+        .queue(Hide).unwrap()
         .queue(EnableMouseCapture).unwrap()
+        .queue(EnableBracketedPaste).unwrap()
         .flush().unwrap();
 
+    // Reader and waiter threads only need to wake the loop up once until it handles their events
+    let (wakeup_sender, wakeup) = bounded(1);
+
     screen.add_container(Rc::new(RefCell::new(Box::new(SimpleTerminalWindow::new(
         5,
         5,
         60,
         15,
-        "1".to_string(),
+        &command,
+        &config,
+        wakeup_sender.clone(),
     )))));
 
     screen.add_container(Rc::new(RefCell::new(Box::new(SimpleTerminalWindow::new(
@@ -38,7 +136,9 @@ fn main() {
         15,
         60,
         15,
-        "2".to_string(),
+        &command,
+        &config,
+        wakeup_sender.clone(),
     )))));
 
     screen.add_container(Rc::new(RefCell::new(Box::new(SimpleTerminalWindow::new(
@@ -46,68 +146,103 @@ fn main() {
         25,
         60,
         15,
-        "3".to_string(),
+        &command,
+        &config,
+        wakeup_sender,
     )))));
 
-    let size = size();
-    let mut current_w = 0;
-    let mut current_h = 0;
+    let mut renderer = Renderer::new(renderer::supports_truecolor());
+    let (mut current_w, mut current_h) = size().unwrap_or((0, 0));
 
-    if size.is_ok() {
-        let size = size.unwrap();
-        current_w = size.0;
-        current_h = size.1;
-        renderer::redraw(&mut stdout, current_w, current_h, &screen);
-    }
-    loop {
-        for con in screen.containers.iter() {
-            con.deref().borrow_mut().update_content();
-        }
-        renderer::redraw(&mut stdout, current_w, current_h, &screen);
-        while poll(Duration::from_millis(0)).unwrap() {
-            let event = read();
-            if event.is_ok() {
-                match event.unwrap() {
-                    Event::Resize(w, h) => {
-                        current_w = w;
-                        current_h = h;
+    let input = spawn_input_thread();
+    let mut frame_pending = true;
+    let mut next_frame = Instant::now();
+    'main: loop {
+        // Only wake up for the next frame if there is something to draw
+        let timeout = if frame_pending {
+            Some(next_frame.saturating_duration_since(Instant::now()))
+        } else {
+            None
+        };
+        // Without input there is no way to use or quit TermUI anymore
+        let mut event = match wait_for_event(&input, &wakeup, timeout) {
+            Ok(event) => event,
+            Err(_) => break 'main
+        };
+        while let Some(current_event) = event {
+            match current_event {
+                Event::Resize(w, h) => {
+                    current_w = w;
+                    current_h = h;
+                    renderer.invalidate();
+                    screen.mark_dirty();
+                }
+                Event::Mouse(mouse_event) => {
+                    let (x, y) = (mouse_event.column, mouse_event.row);
+                    if let MouseEventKind::Down(_) = mouse_event.kind {
+                        screen.check_top_container(x, y);
                     }
-                    Event::Mouse(mouseEvent) => {
-                        match mouseEvent {
-                            MouseEvent::Down(mouse_button, x, y, key_modifiers) => {
-                                screen.check_top_container(x, y);
-                                screen.get_top_container().unwrap().borrow_mut().on_mouse_down(x, y);
-                            }
-                            MouseEvent::Up(mouse_button, x, y, key_modifiers) => {
-                                screen.get_top_container().unwrap().borrow_mut().on_mouse_up(x, y);
-                            }
-                            MouseEvent::Drag(mouse_button, x, y, key_modifiers) => {
-                                screen.get_top_container().unwrap().borrow_mut().on_mouse_drag(x, y);
-                            }
-                            MouseEvent::ScrollUp(x, y, key_modifiers) => {
-                                screen.get_top_container().unwrap().borrow_mut().on_scroll_y(-1);
-                            }
-                            MouseEvent::ScrollDown(x, y, key_modifiers) => {
-                                screen.get_top_container().unwrap().borrow_mut().on_scroll_y(1);
-                            }
-                            _ => {}
-                        };
+                    if let Some(top_container) = screen.get_top_container() {
+                        let mut top_container = top_container.borrow_mut();
+                        // Programs tracking the mouse get the events over their content,
+                        // everything else moves, resizes or scrolls the window
+                        if !top_container.report_mouse(mouse_event) {
+                            match mouse_event.kind {
+                                MouseEventKind::Down(_) => top_container.on_mouse_down(x, y),
+                                MouseEventKind::Up(_) => top_container.on_mouse_up(x, y),
+                                MouseEventKind::Drag(_) => top_container.on_mouse_drag(x, y),
+                                MouseEventKind::ScrollUp => top_container.on_scroll_y(-3),
+                                MouseEventKind::ScrollDown => top_container.on_scroll_y(3),
+                                _ => {}
+                            };
+                        }
+                    }
+                }
+                Event::Key(key_event) => {
+                    if config.quit_key.matches(&key_event) {
+                        break 'main;
                     }
-                    Event::Key(key_event) => {
-                        if key_event.code == KeyCode::Char('c') {
-                            if key_event.modifiers == KeyModifiers::CONTROL {
-                                stdout
-                                    .queue(EnableBlinking).unwrap()
-                                    .queue(DisableMouseCapture).unwrap();
-                                disable_raw_mode().unwrap();
-                            }
+                    if let Some(top_container) = screen.get_top_container() {
+                        if config.paste_key.matches(&key_event) {
+                            let text = screen.clipboard.get().to_string();
+                            top_container.borrow_mut().on_paste(&text);
+                        } else {
+                            top_container.borrow_mut().on_key(key_event.code, key_event.modifiers);
                         }
-
-                        screen.get_top_container().unwrap().borrow_mut().on_key(key_event.code, key_event.modifiers);
                     }
-                    _ => {}
                 }
+                // The host terminal has bracketed paste enabled, so a paste arrives in one piece
+                Event::Paste(text) => {
+                    if let Some(top_container) = screen.get_top_container() {
+                        top_container.borrow_mut().on_paste(&text);
+                    }
+                }
+                Event::FocusGained | Event::FocusLost => {}
             }
+            // Handle everything that is already queued before drawing
+            event = input.try_recv().ok();
+        }
+
+        screen.update_content();
+        // Copies go to the system clipboard too through the host terminal
+        if config.clipboard_to_host {
+            if let Some(text) = screen.clipboard.take_change() {
+                let _ = stdout.write_all(clipboard::osc52("c", &text, true).as_bytes());
+                let _ = stdout.flush();
+            }
+        }
+        // Every window is gone once all of their commands exited
+        if screen.containers.is_empty() {
+            break 'main;
+        }
+        frame_pending |= screen.take_dirty();
+        if frame_pending && Instant::now() >= next_frame {
+            renderer.redraw(&mut stdout, current_w, current_h, &screen).unwrap();
+            frame_pending = false;
+            next_frame = Instant::now() + config.frame_interval();
         }
     }
-}
\ No newline at end of file
+
+    shut_down(&mut screen, &wakeup);
+    restore_terminal();
+}
@@ -0,0 +1,80 @@
+use crate::screen::{Attributes, CharacterCell, Color};
+use unicode_width::UnicodeWidthChar;
+
+// A screen sized grid of cells that every container gets composited into
+pub struct CellBuffer {
+    pub width: u16,
+    pub height: u16,
+    cells: Vec<CharacterCell>,
+}
+
+impl CellBuffer {
+    pub fn new(width: u16, height: u16) -> CellBuffer {
+        CellBuffer {
+            width,
+            height,
+            cells: vec![CharacterCell::new(' ', Color::Default, Color::Default, Attributes::empty()); width as usize * height as usize],
+        }
+    }
+
+    pub fn get(&self, x: u16, y: u16) -> &CharacterCell {
+        &self.cells[x as usize + y as usize * self.width as usize]
+    }
+
+    fn blank_at(&mut self, x: usize, y: usize) {
+        let cell = &mut self.cells[x + y * self.width as usize];
+        *cell = CharacterCell::new(' ', cell.fg, cell.bg, cell.attrs);
+    }
+
+    // Cells outside of the buffer are silently dropped.
+    // Wide characters that lose their other half, by being clipped or partly covered, are replaced by a blank
+    pub fn set(&mut self, x: i32, y: i32, mut cell: CharacterCell) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+        let (x, y) = (x as usize, y as usize);
+        let index = x + y * self.width as usize;
+        let follows_wide = x > 0 && self.cells[index - 1].is_wide();
+        if cell.is_continuation() {
+            if !follows_wide {
+                cell = CharacterCell::new(' ', cell.fg, cell.bg, cell.attrs);
+            }
+        } else if follows_wide {
+            self.blank_at(x - 1, y);
+        }
+        if self.cells[index].is_wide() && x + 1 < self.width as usize {
+            self.blank_at(x + 1, y);
+        }
+        if cell.is_wide() && x + 1 >= self.width as usize {
+            cell = CharacterCell::new(' ', cell.fg, cell.bg, cell.attrs);
+        }
+        self.cells[index] = cell;
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, w: i32, h: i32, cell: CharacterCell) {
+        for y_iterator in y..(y + h) {
+            for x_iterator in x..(x + w) {
+                self.set(x_iterator, y_iterator, cell.clone());
+            }
+        }
+    }
+
+    // Writes a string on a single line, stopping at max_x
+    pub fn put_str(&mut self, x: i32, y: i32, max_x: i32, text: &str, fg: Color, bg: Color) {
+        let mut x = x;
+        for ch in text.chars() {
+            let width = match ch.width() {
+                Some(width) if width > 0 => width as i32,
+                _ => continue
+            };
+            if x + width > max_x {
+                return;
+            }
+            self.set(x, y, CharacterCell::new(ch, fg, bg, Attributes::empty()).with_width(width as u8));
+            if width == 2 {
+                self.set(x + 1, y, CharacterCell::new(' ', fg, bg, Attributes::empty()).with_width(0));
+            }
+            x += width;
+        }
+    }
+}
@@ -1,112 +1,174 @@
-use std::io::{stdout, Write, BufWriter, Stdout, StdoutLock};
+use std::io::{Write, Stdout};
 use crossterm::{
-    ExecutableCommand, QueueableCommand,
-    terminal, cursor, style::{self, Colorize}, Result
+    QueueableCommand,
+    cursor, style::{Attribute, SetAttribute, SetAttributes, SetBackgroundColor, SetForegroundColor}, Result
 };
-use crate::screen::Screen;
+use crate::screen::{Attributes, CharacterCell, Color, Screen};
 use std::ops::Deref;
-use std::cmp::min;
-use std::ffi::CString;
-
-fn draw_rect(stdout: &mut Vec<u8>, x: u16, y: u16, w: u16, h: u16, max_w: u16, max_h: u16) {
-    for x_iterator in x..(x+w) {
-        for y_iterator in y..(y+h) {
-            if (x_iterator < max_w && y_iterator < max_h) && ((x_iterator == x || y_iterator == y) || (x_iterator == x + w - 1 || y_iterator == y + h - 1)) {
-                stdout
-                    .queue(cursor::MoveTo(x_iterator,y_iterator)).unwrap()
-                    .queue(style::PrintStyledContent( "█".white())).unwrap();
-            }
-            else {
-                stdout
-                    .queue(cursor::MoveTo(x_iterator,y_iterator)).unwrap()
-                    .queue(style::PrintStyledContent( "█".black())).unwrap();
-            }
+
+mod buffer;
+
+pub use buffer::CellBuffer;
+
+fn block_cell(color: Color) -> CharacterCell {
+    CharacterCell::new('█', color, Color::Default, Attributes::empty())
+}
+
+fn draw_rect(buffer: &mut CellBuffer, x: i32, y: i32, w: i32, h: i32) {
+    buffer.fill_rect(x, y, w, h, block_cell(Color::Ansi(15)));
+    buffer.fill_rect(x + 1, y + 1, w - 2, h - 2, block_cell(Color::Ansi(0)));
+}
+
+// Keeps track of the host terminal's cursor and style while writing cells,
+// so that only the escape codes that actually change something are emitted
+struct CellWriter {
+    cursor: Option<(u16, u16)>,
+    style: Option<(Color, Color, Attributes)>,
+    truecolor: bool,
+}
+
+impl CellWriter {
+    fn write(&mut self, stdout: &mut Vec<u8>, x: u16, y: u16, cell: &CharacterCell) -> Result<()> {
+        if self.cursor != Some((x, y)) {
+            stdout.queue(cursor::MoveTo(x, y))?;
+        }
+        let style = (cell.fg, cell.bg, cell.attrs);
+        if self.style != Some(style) {
+            stdout
+                .queue(SetAttribute(Attribute::Reset))?
+                .queue(SetAttributes(cell.attrs.to_crossterm()))?
+                .queue(SetForegroundColor(cell.fg.to_crossterm(self.truecolor)))?
+                .queue(SetBackgroundColor(cell.bg.to_crossterm(self.truecolor)))?;
+            self.style = Some(style);
         }
+        stdout.queue(crossterm::style::Print(cell.ch))?;
+        for &ch in &cell.combining {
+            stdout.queue(crossterm::style::Print(ch))?;
+        }
+        self.cursor = Some((x + cell.width as u16, y));
+        Ok(())
     }
 }
 
-fn render_text(stdout: &mut Vec<u8>, x: u16, y: u16, max_h: u16, text: String) {
-    let lines: Vec<&str> = text.split("\n").collect();
-    let mut i = 0;
-    for mut line in lines {
-        if i > max_h {
-            return;
-        }
-        let line_str = line.to_string();
+// Hosts advertise 24-bit color support through COLORTERM, anything else gets RGB downsampled to 256 colors
+pub fn supports_truecolor() -> bool {
+    match std::env::var("COLORTERM") {
+        Ok(value) => value == "truecolor" || value == "24bit",
+        Err(_) => false
+    }
+}
 
-        stdout
-            .queue(cursor::MoveTo(x, y + i)).unwrap()
-            .queue(crossterm::style::Print(line_str.as_str())).unwrap();
+pub struct Renderer {
+    // What the host terminal is currently showing, None when it has to be repainted entirely
+    front: Option<CellBuffer>,
+    last_output_size: usize,
+    truecolor: bool,
+}
 
-        i += 1;
+impl Renderer {
+    pub fn new(truecolor: bool) -> Renderer {
+        Renderer {
+            front: None,
+            last_output_size: 0,
+            truecolor,
+        }
     }
-}
 
-pub fn redraw(stdout: &mut Stdout, w: u16, h: u16, screen: &Screen) -> Result<()> {
-    let mut vec: Vec<u8> = vec![];
-    let mut s = stdout;
-    let mut stdout = vec;
-
-    // Clear the screen
-    draw_rect(&mut stdout, 0, 0, w, h, w, h);
-
-    for con in screen.containers.iter() {
-        let con = con.deref().borrow();
-        // Draw the container border around the window
-        draw_rect(&mut stdout, con.get_x() - 1, con.get_y() - 1, con.get_width() + 2, con.get_height() + 2, w, h);
-        // Draw the container title info
-        let mut info = "".to_string();
-        let title = con.get_title();
-        if title.is_some() {
-            info.push_str(title.unwrap());
-            info.push(' ');
+    pub fn invalidate(&mut self) {
+        self.front = None;
+    }
+
+    fn compose(&self, w: u16, h: u16, screen: &Screen) -> CellBuffer {
+        let mut buffer = CellBuffer::new(w, h);
+
+        // Clear the screen
+        draw_rect(&mut buffer, 0, 0, w as i32, h as i32);
+
+        // Containers are stored bottom to top, so later ones are drawn over earlier ones
+        for con in screen.containers.iter() {
+            let con = con.deref().borrow();
+            let x = con.get_x() as i32;
+            let y = con.get_y() as i32;
+            let width = con.get_width() as i32;
+            let height = con.get_height() as i32;
+            // Draw the container border around the window
+            draw_rect(&mut buffer, x - 1, y - 1, width + 2, height + 2);
+            // Draw the container title info
+            let mut info = "".to_string();
+            if let Some(title) = con.get_title() {
+                info.push_str(title);
+                info.push(' ');
+            }
+            info.push_str(format!("W: {} H: {} X: {} Y: {} Cursor: {:?} {}",
+                                  con.get_width(),
+                                  con.get_height(),
+                                  con.get_x(),
+                                  con.get_y(),
+                                  con.get_cursor(),
+                                  con.get_printed_chars()
+            ).as_str());
+            buffer.put_str(x, y - 1, x + width, &info, Color::Default, Color::Default);
+            // Draw the container's content
+            con.blit(&mut buffer);
+            // Draw the resize handles
+            buffer.put_str(x + width, y + height / 2, x + width + 1, "↔", Color::Default, Color::Default);
+            buffer.put_str(x + width / 2, y + height, x + width / 2 + 1, "↕", Color::Default, Color::Default);
         }
-        info.push_str(format!("W: {} H: {} X: {} Y: {} Cursor: {:?} {}",
-                              con.get_width(),
-                              con.get_height(),
-                              con.get_x(),
-                              con.get_y(),
-                              con.get_cursor(),
-                              con.get_printed_chars()
-        ).as_str());
-        stdout
-            .queue(cursor::MoveTo(con.get_x(), con.get_y() - 1)).unwrap()
-            .queue(crossterm::style::Print(info)).unwrap();
-        // Draw the container's content
-        let mut con_width = con.get_width();
-        let mut con_height = con.get_height();
-        let mut do_render_text = true;
-        if con.get_x() > w || con.get_y() > h - 1 {
-            do_render_text = false;
+
+        // Render some info about TermUI
+        let info_string = format!("Stdout buffer size : {}", self.last_output_size);
+        buffer.put_str(2, 0, w as i32, &info_string, Color::Default, Color::Default);
+
+        // Render dev console
+        if let Some(message) = screen.dev_console.last() {
+            buffer.put_str(0, h as i32 - 1, w as i32, message, Color::Default, Color::Default);
         }
-        if do_render_text {
-            if con.get_x() + con_width > w {
-                con_width -= con.get_x() + con_width - w;
-            }
-            if con.get_y() + con_height > h {
-                con_height -= con.get_y() + con_height - h;
+
+        buffer
+    }
+
+    // Only the cells that changed since the last frame are sent to the host terminal
+    pub fn redraw(&mut self, stdout: &mut Stdout, w: u16, h: u16, screen: &Screen) -> Result<()> {
+        let back = self.compose(w, h, screen);
+        let mut output: Vec<u8> = vec![];
+        let mut writer = CellWriter {
+            cursor: None,
+            style: None,
+            truecolor: self.truecolor,
+        };
+
+        let front = match self.front.take() {
+            Some(front) if front.width == w && front.height == h => Some(front),
+            _ => None
+        };
+        for y in 0..h {
+            for x in 0..w {
+                let cell = back.get(x, y);
+                // Continuations are drawn by the host along with the wide character before them
+                if cell.is_continuation() {
+                    continue;
+                }
+                let changed = match &front {
+                    Some(front) => {
+                        front.get(x, y) != cell || (cell.is_wide() && front.get(x + 1, y) != back.get(x + 1, y))
+                    },
+                    None => true
+                };
+                if changed {
+                    writer.write(&mut output, x, y, cell)?;
+                }
             }
-            render_text(&mut stdout, con.get_x(), con.get_y(), con_height - 1, con.get_content());
         }
-        // Draw the resize handles
-        if con.get_x() + con.get_width() < w {
-            stdout
-                .queue(cursor::MoveTo(con.get_x() + con.get_width(), con.get_y() + con.get_height() / 2)).unwrap()
-                .queue(crossterm::style::Print("↔"))?;
+        if writer.style.is_some() {
+            output.queue(SetAttribute(Attribute::Reset))?;
         }
-        if con.get_y() + con.get_height() < h {
-            stdout
-                .queue(cursor::MoveTo(con.get_x() + con.get_width() / 2, con.get_y() + con.get_height())).unwrap()
-                .queue(crossterm::style::Print("↕"))?;
+
+        if !output.is_empty() {
+            stdout.write_all(&output)?;
+            stdout.flush()?;
         }
+        self.last_output_size = output.len();
+        self.front = Some(back);
+        Ok(())
     }
-
-    // Render some info about TermUI
-    let info_string = format!("Stdout buffer size : {}", stdout.len());
-    stdout
-        .queue(cursor::MoveTo(2, 0)).unwrap()
-        .queue(crossterm::style::Print(info_string));
-    // Render dev console
-    s.write_all(&stdout);
-    Ok(())
-}
\ No newline at end of file
+}
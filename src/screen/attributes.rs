@@ -0,0 +1,59 @@
+use bitflags::bitflags;
+use crossterm::style::Attribute;
+
+bitflags! {
+    pub struct Attributes: u16 {
+        const BOLD = 1 << 0;
+        const DIM = 1 << 1;
+        const ITALIC = 1 << 2;
+        const UNDERLINE = 1 << 3;
+        const BLINK = 1 << 4;
+        const REVERSE = 1 << 5;
+        const HIDDEN = 1 << 6;
+        const STRIKETHROUGH = 1 << 7;
+    }
+}
+
+const CROSSTERM_ATTRIBUTES: [(Attributes, Attribute); 8] = [
+    (Attributes::BOLD, Attribute::Bold),
+    (Attributes::DIM, Attribute::Dim),
+    (Attributes::ITALIC, Attribute::Italic),
+    (Attributes::UNDERLINE, Attribute::Underlined),
+    (Attributes::BLINK, Attribute::SlowBlink),
+    (Attributes::REVERSE, Attribute::Reverse),
+    (Attributes::HIDDEN, Attribute::Hidden),
+    (Attributes::STRIKETHROUGH, Attribute::CrossedOut),
+];
+
+impl Attributes {
+    // Applies a single SGR parameter, anything that isn't an attribute is ignored
+    pub fn apply_sgr(&mut self, param: i64) {
+        match param {
+            1 => self.insert(Attributes::BOLD),
+            2 => self.insert(Attributes::DIM),
+            3 => self.insert(Attributes::ITALIC),
+            4 => self.insert(Attributes::UNDERLINE),
+            5 | 6 => self.insert(Attributes::BLINK),
+            7 => self.insert(Attributes::REVERSE),
+            8 => self.insert(Attributes::HIDDEN),
+            9 => self.insert(Attributes::STRIKETHROUGH),
+            22 => self.remove(Attributes::BOLD | Attributes::DIM),
+            23 => self.remove(Attributes::ITALIC),
+            24 => self.remove(Attributes::UNDERLINE),
+            25 => self.remove(Attributes::BLINK),
+            27 => self.remove(Attributes::REVERSE),
+            28 => self.remove(Attributes::HIDDEN),
+            29 => self.remove(Attributes::STRIKETHROUGH),
+            _ => {}
+        }
+    }
+
+    pub fn to_crossterm(self) -> crossterm::style::Attributes {
+        let attributes: Vec<Attribute> = CROSSTERM_ATTRIBUTES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, attribute)| *attribute)
+            .collect();
+        attributes.as_slice().into()
+    }
+}
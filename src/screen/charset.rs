@@ -0,0 +1,60 @@
+// The character sets that can be designated to G0 and G1
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Charset {
+    Ascii,
+    // Line drawing characters in place of the lowercase letters, what ncurses uses for borders
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    // The designation byte following ESC ( or ESC ), None for sets that aren't supported
+    pub fn from_designator(byte: u8) -> Option<Charset> {
+        match byte {
+            // The UK set only differs by its pound sign, it is close enough to ASCII
+            b'B' | b'A' | b'1' => Some(Charset::Ascii),
+            b'0' | b'2' => Some(Charset::DecSpecialGraphics),
+            _ => None
+        }
+    }
+
+    pub fn map(self, ch: char) -> char {
+        match self {
+            Charset::Ascii => ch,
+            Charset::DecSpecialGraphics => match ch {
+                '_' => '\u{A0}',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                _ => ch
+            }
+        }
+    }
+}
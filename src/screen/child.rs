@@ -0,0 +1,345 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag, open};
+use nix::ioctl_write_ptr_bad;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt, Winsize};
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::stat::Mode;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, pipe2, tcgetpgrp, ForkResult, Pid};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::raw::{c_char, c_void};
+use std::ffi::CString;
+use core::ptr;
+use libc::{TIOCSCTTY, TIOCSWINSZ};
+use crate::config::Config;
+use super::Command;
+
+ioctl_write_ptr_bad!(set_window_size, TIOCSWINSZ, Winsize);
+
+// Variables describing the host terminal, they would be wrong inside of a window
+const HOST_TERMINAL_VARIABLES: [&str; 6] = ["TERM", "COLORTERM", "TERM_PROGRAM", "TERM_PROGRAM_VERSION", "LINES", "COLUMNS"];
+
+// Children inherit TermUI's own environment with the terminal related variables replaced,
+// then the command's own variables are set on top of it
+pub fn child_environment(config: &Config, command: &Command, window_id: usize) -> Vec<CString> {
+    let mut env: Vec<CString> = std::env::vars_os()
+        .filter(|(name, _)| !HOST_TERMINAL_VARIABLES.iter().any(|variable| name == variable))
+        .filter(|(name, _)| !command.env.iter().any(|(variable, _)| name == variable.as_str()))
+        .filter_map(|(name, value)| {
+            let mut entry = name.into_vec();
+            entry.push(b'=');
+            entry.extend(value.into_vec());
+            CString::new(entry).ok()
+        })
+        .collect();
+    let overrides = [
+        format!("TERM={}", config.term),
+        "COLORTERM=truecolor".to_string(),
+        format!("TERMUI_WINDOW_ID={}", window_id),
+    ];
+    env.extend(overrides.iter().filter_map(|entry| CString::new(entry.as_str()).ok()));
+    env.extend(command.env.iter().filter_map(|(name, value)| CString::new(format!("{}={}", name, value)).ok()));
+    env
+}
+
+// What went wrong in a forked child before it could exec, written to the parent through a pipe
+const CHILD_CHDIR_FAILED: u8 = 0;
+const CHILD_EXEC_FAILED: u8 = 1;
+
+// Runs in the forked child, so it sticks to async-signal-safe calls
+unsafe fn report_child_error(fd: RawFd, stage: u8) {
+    let errno = errno::errno().0.to_le_bytes();
+    let report = [stage, errno[0], errno[1], errno[2], errno[3]];
+    libc::write(fd, report.as_ptr() as *const c_void, report.len());
+}
+
+// Waits for the child to exec, the pipe is closed on exec so reading nothing means it succeeded
+fn read_child_error(mut pipe: File, command: &Command) -> Option<String> {
+    let mut report = [0u8; 5];
+    loop {
+        match pipe.read_exact(&mut report) {
+            Ok(()) => break,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return None
+        }
+    }
+    let error = Error::from_raw_os_error(i32::from_le_bytes([report[1], report[2], report[3], report[4]]));
+    match report[0] {
+        CHILD_CHDIR_FAILED => {
+            let cwd = command.cwd.as_ref().map(|cwd| cwd.display().to_string()).unwrap_or_default();
+            Some(format!("Could not change directory to {}: {}", cwd, error))
+        },
+        _ => Some(format!("Could not run {}: {}", command, error))
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum ExitStatus {
+    Code(i32),
+    Signal(Signal),
+    // Not even the pty or the process could be set up
+    NotStarted,
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitStatus::Code(code) => write!(f, "exited with {}", code),
+            ExitStatus::Signal(signal) => write!(f, "killed by {}", signal.as_str()),
+            ExitStatus::NotStarted => write!(f, "not started")
+        }
+    }
+}
+
+// What a window's reader and waiter threads pass on to it, the exit comes after the output written before it
+pub enum ChildEvent {
+    Output(Vec<u8>),
+    Exited(ExitStatus),
+}
+
+fn winsize(width: u16, height: u16) -> Winsize {
+    Winsize {
+        ws_row: height,
+        ws_col: width,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+// Opens a pty of the given size, returns its master and slave sides
+fn open_pty(width: u16, height: u16) -> nix::Result<(File, File)> {
+    // Descriptors are close-on-exec so that children don't keep each other's ptys open
+    let master_fd = posix_openpt(OFlag::O_RDWR | OFlag::O_CLOEXEC)?;
+    grantpt(&master_fd)?;
+    unlockpt(&master_fd)?;
+    let slave_name = unsafe { ptsname(&master_fd) }?;
+    let slave_fd = open(Path::new(&slave_name), OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC, Mode::empty())?;
+    let slave = unsafe { File::from_raw_fd(slave_fd) };
+    let master = unsafe { File::from_raw_fd(master_fd.into_raw_fd()) };
+    unsafe { set_window_size(master.as_raw_fd(), &winsize(width, height)) }?;
+    // The reader and writer threads wait on it themselves, so that writing to a child that doesn't read can be given up
+    fcntl(master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    Ok((master, slave))
+}
+
+// How long the exit waits for output the child wrote just before exiting
+const OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+// How often a blocked write checks whether the child is still there to read it
+const WRITE_RETRY_INTERVAL: i32 = 100;
+
+// How many chunks of output can wait for the window, the reader and so the child are stopped once they are all taken
+const OUTPUT_CHUNKS: usize = 64;
+
+// Forwards everything written to the pty until the child side is closed, or the child exited.
+// Dropping output_done tells the waiter thread that everything was forwarded
+fn read_output(mut master: File, events: Sender<ChildEvent>, wakeup: Sender<()>, output_done: Sender<()>, exited: Arc<AtomicBool>) {
+    let mut buf = [0; 1024];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            // Whatever is still running in the background after the child exited doesn't belong to the window anymore
+            Ok(_) if exited.load(Ordering::Acquire) => break,
+            Ok(n) => {
+                // Blocks while the window is behind, an error means it is gone
+                if events.send(ChildEvent::Output(buf[..n].to_vec())).is_err() {
+                    break;
+                }
+                // A full channel means the main loop is already going to wake up
+                let _ = wakeup.try_send(());
+            }
+            // The master is non-blocking for the writer thread's sake
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                let _ = poll(&mut [PollFd::new(master.as_raw_fd(), PollFlags::POLLIN)], -1);
+            }
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            // EIO once the child side of the pty is closed
+            Err(_) => break
+        }
+    }
+    drop(output_done);
+}
+
+// Writes what the window sends to the child, a child that doesn't read its input only holds up this thread
+// instead of the main loop. What is left is dropped once the child exited
+fn write_input(mut master: File, input: Receiver<Vec<u8>>, exited: Arc<AtomicBool>) {
+    for bytes in input.iter() {
+        let mut written = 0;
+        while written < bytes.len() {
+            if exited.load(Ordering::Acquire) {
+                return;
+            }
+            match master.write(&bytes[written..]) {
+                Ok(n) => written += n,
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                    let _ = poll(&mut [PollFd::new(master.as_raw_fd(), PollFlags::POLLOUT)], WRITE_RETRY_INTERVAL);
+                }
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return
+            }
+        }
+    }
+}
+
+// Reaps the child as soon as it exits, even when something it left running in the background keeps the pty open
+fn wait_for_exit(pid: Pid, events: Sender<ChildEvent>, wakeup: Sender<()>, output_done: Receiver<()>, exited: Arc<AtomicBool>) {
+    let status = loop {
+        match waitpid(pid, None) {
+            Ok(WaitStatus::Exited(_, code)) => break ExitStatus::Code(code),
+            Ok(WaitStatus::Signaled(_, signal, _)) => break ExitStatus::Signal(signal),
+            Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(_) => return
+        }
+    };
+    let _ = output_done.recv_timeout(OUTPUT_GRACE);
+    exited.store(true, Ordering::Release);
+    let _ = events.send(ChildEvent::Exited(status));
+    let _ = wakeup.try_send(());
+}
+
+// A command running on its own pty
+pub struct Child {
+    master: File,
+    pub pid: Pid,
+    pub started: Instant,
+    // Output and then the exit, dropping it stops the reader thread of a child that is replaced
+    pub events: Receiver<ChildEvent>,
+    input: Sender<Vec<u8>>,
+}
+
+impl Child {
+    // Errors happening before the command could exec are returned along with the child, which exits right away.
+    // Running out of ptys, descriptors or processes is an error without a child
+    pub fn spawn(
+        command: &Command,
+        env: &[CString],
+        width: u16,
+        height: u16,
+        wakeup: Sender<()>,
+    ) -> Result<(Child, Option<String>), String> {
+        let (master, slave) = open_pty(width, height).map_err(|e| format!("Could not open a pty: {}", e))?;
+        // The reader and writer threads get their own descriptors so they can drop them when the child goes away
+        let reader = master.try_clone().map_err(|e| format!("Could not open a pty: {}", e))?;
+        let writer = master.try_clone().map_err(|e| format!("Could not open a pty: {}", e))?;
+
+        // Everything exec needs is allocated before forking
+        let path = command.env.iter().rev()
+            .find(|(name, _)| name == "PATH")
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var("PATH").ok());
+        let program = CString::new(command.resolve(path.as_deref()).into_os_string().into_vec()).unwrap_or_default();
+        let args: Vec<CString> = std::iter::once(command.argv0())
+            .chain(command.args.iter().cloned())
+            .filter_map(|arg| CString::new(arg).ok())
+            .collect();
+        let cwd = command.cwd.as_ref().and_then(|cwd| CString::new(cwd.clone().into_os_string().into_vec()).ok());
+        let mut arg_pointers: Vec<*const c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        arg_pointers.push(ptr::null());
+        let mut env_pointers: Vec<*const c_char> = env.iter().map(|entry| entry.as_ptr()).collect();
+        env_pointers.push(ptr::null());
+        let (error_read, error_write) = pipe2(OFlag::O_CLOEXEC).map_err(|e| format!("Could not create a pipe: {}", e))?;
+        let (error_read, error_write) = unsafe { (File::from_raw_fd(error_read), File::from_raw_fd(error_write)) };
+        let (slave_fd, error_fd) = (slave.as_raw_fd(), error_write.as_raw_fd());
+        let mut no_signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::sigemptyset(&mut no_signals) };
+
+        let pid = match fork() {
+            Ok(ForkResult::Parent { child, .. }) => {
+                child
+            }
+            Ok(ForkResult::Child) => {
+                unsafe {
+                    // Rust ignores SIGPIPE and ignored signals stay ignored across exec, commands expect the defaults
+                    libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                    libc::sigprocmask(libc::SIG_SETMASK, &no_signals, ptr::null_mut());
+                    libc::setsid();
+                    libc::ioctl(slave_fd, TIOCSCTTY, 0);
+                    libc::dup2(slave_fd, 0); // stdin
+                    libc::dup2(slave_fd, 1); // stdout
+                    libc::dup2(slave_fd, 2); // stderr
+                    if let Some(cwd) = &cwd {
+                        if libc::chdir(cwd.as_ptr()) == -1 {
+                            report_child_error(error_fd, CHILD_CHDIR_FAILED);
+                            libc::_exit(127);
+                        }
+                    }
+                    libc::execve(program.as_ptr(), arg_pointers.as_ptr(), env_pointers.as_ptr());
+                    report_child_error(error_fd, CHILD_EXEC_FAILED);
+                    libc::_exit(127);
+                }
+            }
+            Err(e) => return Err(format!("Could not start a process: {}", e)),
+        };
+        // Only the child keeps the slave side open, so reading the master ends when the child is gone
+        drop(slave);
+        drop(error_write);
+        let error = read_child_error(error_read, command);
+
+        let (event_sender, events) = bounded(OUTPUT_CHUNKS);
+        let (output_done_sender, output_done) = bounded(0);
+        let exited = Arc::new(AtomicBool::new(false));
+        {
+            let event_sender = event_sender.clone();
+            let wakeup = wakeup.clone();
+            let exited = exited.clone();
+            std::thread::Builder::new()
+                .name("pty reader".to_string())
+                .spawn(move || read_output(reader, event_sender, wakeup, output_done_sender, exited))
+                .unwrap();
+        }
+        let (input, input_receiver) = unbounded();
+        {
+            let exited = exited.clone();
+            std::thread::Builder::new()
+                .name("pty writer".to_string())
+                .spawn(move || write_input(writer, input_receiver, exited))
+                .unwrap();
+        }
+        std::thread::Builder::new()
+            .name("child waiter".to_string())
+            .spawn(move || wait_for_exit(pid, event_sender, wakeup, output_done, exited))
+            .unwrap();
+
+        let child = Child {
+            master,
+            pid,
+            started: Instant::now(),
+            events,
+            input,
+        };
+        Ok((child, error))
+    }
+
+    // The child leads its own process group, but a job control shell puts the jobs it runs in groups of their own.
+    // The one in the foreground is the pty's foreground group, so it gets the signal too
+    pub fn signal(&self, signal: Signal) {
+        if let Ok(foreground) = tcgetpgrp(self.master.as_raw_fd()) {
+            if foreground != self.pid {
+                let _ = killpg(foreground, signal);
+            }
+        }
+        let _ = killpg(self.pid, signal);
+    }
+
+    // Hands the bytes to the writer thread, so this never blocks
+    pub fn write(&self, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            let _ = self.input.send(bytes.to_vec());
+        }
+    }
+
+    // The kernel sends SIGWINCH to the child when the size changes
+    pub fn resize(&self, width: u16, height: u16) {
+        unsafe { set_window_size(self.master.as_raw_fd(), &winsize(width, height)).unwrap() };
+    }
+}
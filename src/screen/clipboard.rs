@@ -0,0 +1,40 @@
+// What a program asked for through OSC 52, the window carries it out since only it can reach the shared clipboard
+pub enum ClipboardRequest {
+    Set(String),
+    // Holds the selection parameter to answer with and whether the query ended with BEL
+    Query(String, bool),
+}
+
+// The clipboard shared by every window
+#[derive(Default)]
+pub struct Clipboard {
+    content: String,
+    // Set when the content changed since it was last taken to the host terminal
+    changed: bool,
+}
+
+impl Clipboard {
+    pub fn get(&self) -> &str {
+        &self.content
+    }
+
+    pub fn set(&mut self, content: String) {
+        self.content = content;
+        self.changed = true;
+    }
+
+    // The content if it changed since the last call
+    pub fn take_change(&mut self) -> Option<String> {
+        if std::mem::replace(&mut self.changed, false) {
+            Some(self.content.clone())
+        } else {
+            None
+        }
+    }
+}
+
+// The OSC 52 sequence setting a clipboard to the given text, also used to answer queries
+pub fn osc52(selection: &str, text: &str, bell_terminated: bool) -> String {
+    let terminator = if bell_terminated { "\x07" } else { "\x1B\\" };
+    format!("\x1B]52;{};{}{}", selection, base64::encode(text), terminator)
+}
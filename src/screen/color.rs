@@ -0,0 +1,116 @@
+use std::slice::Iter;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Color {
+    // The host terminal's own foreground or background color
+    Default,
+    // One of the 16 ANSI colors, 8 to 15 being the bright variants
+    Ansi(u8),
+    // An entry of the 256 colors palette
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    // Parses what follows a 38 or 48 SGR parameter, either "5;n" or "2;r;g;b"
+    pub fn from_sgr_params(params: &mut Iter<i64>) -> Option<Color> {
+        let mut next = || params.next().map(|&p| p.clamp(0, 255) as u8);
+        match next()? {
+            5 => Some(Color::Indexed(next()?)),
+            2 => Some(Color::Rgb(next()?, next()?, next()?)),
+            _ => None
+        }
+    }
+
+    pub fn to_crossterm(self, truecolor: bool) -> crossterm::style::Color {
+        use crossterm::style::Color as C;
+        match self {
+            Color::Default => C::Reset,
+            Color::Ansi(index) => match index {
+                0 => C::Black,
+                1 => C::DarkRed,
+                2 => C::DarkGreen,
+                3 => C::DarkYellow,
+                4 => C::DarkBlue,
+                5 => C::DarkMagenta,
+                6 => C::DarkCyan,
+                7 => C::Grey,
+                8 => C::DarkGrey,
+                9 => C::Red,
+                10 => C::Green,
+                11 => C::Yellow,
+                12 => C::Blue,
+                13 => C::Magenta,
+                14 => C::Cyan,
+                _ => C::White,
+            },
+            Color::Indexed(index) => C::AnsiValue(index),
+            Color::Rgb(r, g, b) => {
+                if truecolor {
+                    C::Rgb { r, g, b }
+                } else {
+                    C::AnsiValue(rgb_to_indexed(r, g, b))
+                }
+            }
+        }
+    }
+}
+
+// Values of the 6 levels used by each channel of the 256 colors cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn nearest_cube_level(value: u8) -> usize {
+    CUBE_LEVELS
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, &level)| (level as i32 - value as i32).abs())
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    let dr = a.0 as i32 - b.0 as i32;
+    let dg = a.1 as i32 - b.1 as i32;
+    let db = a.2 as i32 - b.2 as i32;
+    dr * dr + dg * dg + db * db
+}
+
+// Picks the closest entry among the color cube (16-231) and the grayscale ramp (232-255)
+fn rgb_to_indexed(r: u8, g: u8, b: u8) -> u8 {
+    let (ri, gi, bi) = (nearest_cube_level(r), nearest_cube_level(g), nearest_cube_level(b));
+    let cube = (CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]);
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let gray_step = ((average.max(8) - 8) / 10).min(23);
+    let gray_level = (8 + gray_step * 10) as u8;
+    let gray_index = 232 + gray_step as usize;
+
+    if distance((r, g, b), (gray_level, gray_level, gray_level)) < distance((r, g, b), cube) {
+        gray_index as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rgb_to_indexed;
+
+    #[test]
+    fn exact_cube_colors() {
+        assert_eq!(rgb_to_indexed(0, 0, 0), 16);
+        assert_eq!(rgb_to_indexed(255, 0, 0), 196);
+        assert_eq!(rgb_to_indexed(95, 135, 175), 67);
+        assert_eq!(rgb_to_indexed(255, 255, 255), 231);
+    }
+
+    #[test]
+    fn greys_between_cube_levels_use_the_ramp() {
+        assert_eq!(rgb_to_indexed(128, 128, 128), 244);
+        assert_eq!(rgb_to_indexed(30, 30, 30), 234);
+        assert_eq!(rgb_to_indexed(238, 238, 238), 255);
+        // Saturated colors stay in the cube
+        assert_eq!(rgb_to_indexed(200, 40, 40), 160);
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// What a window runs, set up like std::process::Command
+#[derive(Clone)]
+pub struct Command {
+    pub program: String,
+    pub args: Vec<String>,
+    // Directory the program starts in, TermUI's own one when None
+    pub cwd: Option<PathBuf>,
+    // Variables set on top of the inherited environment
+    pub env: Vec<(String, String)>,
+    // Login shells get a '-' in front of their argv[0]
+    pub login: bool,
+}
+
+impl Command {
+    pub fn new(program: &str) -> Command {
+        Command {
+            program: program.to_string(),
+            args: vec![],
+            cwd: None,
+            env: vec![],
+            login: false,
+        }
+    }
+
+    // The user's $SHELL started as a login shell
+    pub fn login_shell() -> Command {
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        let mut command = Command::new(&shell);
+        command.login = true;
+        command
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, args: I) -> Command {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        Path::new(&self.program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&self.program)
+    }
+
+    pub fn argv0(&self) -> String {
+        if self.login {
+            format!("-{}", self.name())
+        } else {
+            self.program.clone()
+        }
+    }
+
+    // Looks the program up in the given PATH the way a shell would, names containing a slash are used as they are
+    pub fn resolve(&self, path: Option<&str>) -> PathBuf {
+        if self.program.contains('/') {
+            return PathBuf::from(&self.program);
+        }
+        path.unwrap_or("/usr/local/bin:/usr/bin:/bin")
+            .split(':')
+            .map(|directory| Path::new(if directory.is_empty() { "." } else { directory }).join(&self.program))
+            .find(|candidate| candidate.is_file())
+            .unwrap_or_else(|| PathBuf::from(&self.program))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
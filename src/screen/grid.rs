@@ -0,0 +1,1260 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use unicode_width::UnicodeWidthChar;
+use vte::Perform;
+use super::{Attributes, CharacterCell, Color};
+use super::charset::Charset;
+use super::clipboard::ClipboardRequest;
+use super::mouse::{MouseEncoding, MouseTracking};
+
+pub struct EmbedGrid {
+    pub printed_chars: usize,
+    pub cursor: (usize, usize),
+    // The visible screen, one row of width cells per line
+    lines: Vec<Row>,
+    // Lines that scrolled off the top, oldest first
+    scrollback: VecDeque<Row>,
+    scrollback_limit: usize,
+    // How many lines went into the scrollback so far, lets views stay anchored to their content
+    pub scrolled_lines: usize,
+    // The scroll region set by DECSTBM, both rows inclusive
+    scroll_top: usize,
+    scroll_bottom: usize,
+    // The primary screen's content while the alternate screen is shown
+    primary_lines: Option<Vec<Row>>,
+    saved_cursor: SavedCursor,
+    pub cursor_visible: bool,
+    // DECCKM, cursor keys are sent with SS3 instead of CSI when set
+    pub application_cursor_keys: bool,
+    pub mouse_tracking: MouseTracking,
+    pub mouse_encoding: MouseEncoding,
+    // DECSET 2004, pastes are wrapped in markers when set
+    pub bracketed_paste: bool,
+    // DECAWM, when off printing at the last column keeps overwriting it
+    autowrap: bool,
+    // Set after printing at the last column, the wrap only happens once the next character is printed
+    wrap_pending: bool,
+    // One entry per column, true where a tab stop is set
+    tab_stops: Vec<bool>,
+    // G0 and G1, SO and SI pick the one characters are printed with
+    charsets: [Charset; 2],
+    active_charset: usize,
+    // The last graphic character printed, after the charset mapping, for REP
+    last_printed: Option<char>,
+    fg_color: Color,
+    bg_color: Color,
+    attrs: Attributes,
+    pub width: usize,
+    pub height: usize,
+    pub dev_console: Vec<String>,
+    // Answers to the program's queries, waiting to be written back to it
+    pub responses: Vec<u8>,
+    // Set through OSC 0, 1 and 2, None until the program sets them
+    pub title: Option<String>,
+    pub icon_name: Option<String>,
+    // The directory reported through OSC 7
+    pub cwd: Option<PathBuf>,
+    // OSC 52 requests, waiting for the window to carry them out
+    pub clipboard_requests: Vec<ClipboardRequest>
+}
+
+#[derive(Clone)]
+struct Row {
+    cells: Vec<CharacterCell>,
+    // Set when the text went on to the next row because it reached the end of this one,
+    // which lets resizing join the rows back into a single line
+    wrapped: bool,
+    // Set when the row wrapped early because a wide character didn't fit, its last cell is then only padding
+    padded: bool,
+}
+
+impl Row {
+    fn new(mut cells: Vec<CharacterCell>, width: usize) -> Row {
+        cells.resize(width, default_blank_cell());
+        Row {
+            cells,
+            wrapped: false,
+            padded: false,
+        }
+    }
+}
+
+impl Deref for Row {
+    type Target = Vec<CharacterCell>;
+
+    fn deref(&self) -> &Vec<CharacterCell> {
+        &self.cells
+    }
+}
+
+impl DerefMut for Row {
+    fn deref_mut(&mut self) -> &mut Vec<CharacterCell> {
+        &mut self.cells
+    }
+}
+
+// What DECSC saves and DECRC restores
+#[derive(Copy, Clone)]
+struct SavedCursor {
+    position: (usize, usize),
+    fg_color: Color,
+    bg_color: Color,
+    attrs: Attributes,
+    charsets: [Charset; 2],
+    active_charset: usize,
+}
+
+impl SavedCursor {
+    fn new() -> SavedCursor {
+        SavedCursor {
+            position: (0, 0),
+            fg_color: Color::Default,
+            bg_color: Color::Default,
+            attrs: Attributes::empty(),
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+        }
+    }
+}
+
+// Keeps streams of combining characters from growing a cell forever
+const MAX_COMBINING_CHARS: usize = 8;
+
+// Larger than any grid dimension, counts are capped to it so that the arithmetic done with them can't overflow
+const MAX_COUNT: i64 = u16::MAX as i64;
+
+// CSI counts and positions treat a missing or zero parameter as 1
+fn count_param(params: &[i64], index: usize) -> i64 {
+    match params.get(index) {
+        Some(&p) if p > 0 => p.min(MAX_COUNT),
+        _ => 1
+    }
+}
+
+// CSI erase modes default to 0
+fn mode_param(params: &[i64]) -> i64 {
+    params.first().copied().unwrap_or(0)
+}
+
+fn default_blank_cell() -> CharacterCell {
+    CharacterCell::new(' ', Color::Default, Color::Default, Attributes::empty())
+}
+
+// Blanks at the end of a line that reflowing can drop without changing what the line looks like
+fn is_trailing_blank(cell: &CharacterCell) -> bool {
+    cell.ch == ' ' && cell.width == 1 && cell.combining.is_empty() && cell.bg == Color::Default && cell.attrs.is_empty()
+}
+
+// Splits lines into rows of the given width, also returns where the cell at cursor (line, index in the line) ended up
+fn rewrap(lines: &[Vec<CharacterCell>], width: usize, cursor: (usize, usize)) -> (Vec<Row>, (usize, usize)) {
+    let mut rows = vec![];
+    let mut new_cursor = (0, 0);
+    for (line_index, line) in lines.iter().enumerate() {
+        let mut cells: Vec<CharacterCell> = vec![];
+        for (index, cell) in line.iter().enumerate() {
+            // Continuations are moved along with the wide character before them
+            if cell.is_continuation() {
+                if (line_index, index) == cursor {
+                    new_cursor = (rows.len(), cells.len().saturating_sub(1));
+                }
+                continue;
+            }
+            let cell = if cell.width as usize > width { default_blank_cell() } else { cell.clone() };
+            if cells.len() + cell.width as usize > width {
+                let padded = cells.len() < width;
+                let mut row = Row::new(cells, width);
+                row.wrapped = true;
+                row.padded = padded;
+                rows.push(row);
+                cells = vec![];
+            }
+            if (line_index, index) == cursor {
+                new_cursor = (rows.len(), cells.len());
+            }
+            if cell.is_wide() {
+                let continuation = CharacterCell::new(' ', cell.fg, cell.bg, cell.attrs).with_width(0);
+                cells.push(cell);
+                cells.push(continuation);
+            } else {
+                cells.push(cell);
+            }
+        }
+        rows.push(Row::new(cells, width));
+    }
+    (rows, new_cursor)
+}
+
+// The version as a single number for DA2, 1.2.3 becomes 10203
+fn version_number() -> u32 {
+    let part = |part: &str| part.parse::<u32>().unwrap_or(0);
+    part(env!("CARGO_PKG_VERSION_MAJOR")) * 10000 + part(env!("CARGO_PKG_VERSION_MINOR")) * 100 + part(env!("CARGO_PKG_VERSION_PATCH"))
+}
+
+// The text of a title, which the parser split on semicolons. An empty text resets the title
+fn osc_text(params: &[&[u8]]) -> Option<String> {
+    let text: String = String::from_utf8_lossy(&params.join(&b';'))
+        .chars()
+        .filter(|ch| !ch.is_control())
+        .collect();
+    if text.is_empty() { None } else { Some(text) }
+}
+
+// The path of a file://host/path URL, with its percent encoded bytes decoded
+fn parse_file_url(url: &[u8]) -> Option<PathBuf> {
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&byte| byte == b'/')?..];
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = path.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if path[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            },
+            _ => {
+                decoded.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(OsString::from_vec(decoded)))
+}
+
+fn default_tab_stops(width: usize) -> Vec<bool> {
+    (0..width).map(|x| x % 8 == 0 && x > 0).collect()
+}
+
+impl EmbedGrid {
+    pub fn new(width: usize, height: usize, scrollback_limit: usize) -> EmbedGrid {
+        let mut grid = EmbedGrid {
+            printed_chars: 0,
+            cursor: (0, 0),
+            lines: vec![],
+            scrollback: VecDeque::new(),
+            scrollback_limit,
+            scrolled_lines: 0,
+            scroll_top: 0,
+            scroll_bottom: height.saturating_sub(1),
+            primary_lines: None,
+            saved_cursor: SavedCursor::new(),
+            cursor_visible: true,
+            application_cursor_keys: false,
+            mouse_tracking: MouseTracking::Off,
+            mouse_encoding: MouseEncoding::Default,
+            bracketed_paste: false,
+            autowrap: true,
+            wrap_pending: false,
+            tab_stops: default_tab_stops(width),
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+            last_printed: None,
+            fg_color: Color::Default,
+            bg_color: Color::Default,
+            attrs: Attributes::empty(),
+            width,
+            height,
+            dev_console: vec![],
+            responses: vec![],
+            title: None,
+            icon_name: None,
+            cwd: None,
+            clipboard_requests: vec![]
+        };
+        grid.lines = vec![grid.blank_line(); height];
+        grid
+    }
+
+    // Puts every mode a program can set back to its default while keeping the content of the primary screen,
+    // so that the next program doesn't inherit them
+    pub fn reset_modes(&mut self) {
+        self.leave_alternate_screen(false);
+        self.scroll_top = 0;
+        self.scroll_bottom = self.height.saturating_sub(1);
+        self.saved_cursor = SavedCursor::new();
+        self.cursor_visible = true;
+        self.application_cursor_keys = false;
+        self.mouse_tracking = MouseTracking::Off;
+        self.mouse_encoding = MouseEncoding::Default;
+        self.bracketed_paste = false;
+        self.autowrap = true;
+        self.wrap_pending = false;
+        self.tab_stops = default_tab_stops(self.width);
+        self.charsets = [Charset::Ascii; 2];
+        self.active_charset = 0;
+        self.last_printed = None;
+        self.fg_color = Color::Default;
+        self.bg_color = Color::Default;
+        self.attrs = Attributes::empty();
+    }
+
+    // The primary screen gets its lines reflowed to the new width, the alternate screen is only cropped or extended
+    // since the programs using it redraw everything on resize anyway
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.wrap_pending = false;
+        self.tab_stops = default_tab_stops(width);
+        self.scroll_top = 0;
+        self.scroll_bottom = height.saturating_sub(1);
+
+        if let Some(primary_lines) = self.primary_lines.take() {
+            let (primary_lines, cursor) = self.reflow(primary_lines, self.saved_cursor.position);
+            self.primary_lines = Some(primary_lines);
+            self.saved_cursor.position = cursor;
+            for row in &mut self.lines {
+                row.resize(width, default_blank_cell());
+            }
+            self.lines.resize(height, Row::new(vec![], width));
+            self.move_cursor_to(self.cursor.0 as i64, self.cursor.1 as i64);
+        } else {
+            let lines = std::mem::take(&mut self.lines);
+            let (lines, cursor) = self.reflow(lines, self.cursor);
+            self.lines = lines;
+            self.cursor = cursor;
+        }
+    }
+
+    // Joins the wrapped rows of the scrollback and the given screen back into lines, then splits them at the new width.
+    // Returns the new screen and the cursor position on it, the cursor keeps pointing at the same character
+    fn reflow(&mut self, screen: Vec<Row>, cursor: (usize, usize)) -> (Vec<Row>, (usize, usize)) {
+        let cursor_row = self.scrollback.len() + cursor.1.min(screen.len().saturating_sub(1));
+        let mut rows: Vec<Row> = self.scrollback.drain(..).chain(screen).collect();
+
+        // Empty rows under the cursor would otherwise push content into the scrollback when shrinking
+        while rows.len() > cursor_row + 1 && rows.last().is_some_and(|row| !row.wrapped && row.iter().all(is_trailing_blank)) {
+            rows.pop();
+        }
+
+        let mut lines = vec![];
+        let mut line: Vec<CharacterCell> = vec![];
+        let mut line_cursor = (0, 0);
+        for (index, row) in rows.into_iter().enumerate() {
+            let mut keep = 0;
+            if index == cursor_row {
+                line_cursor = (lines.len(), line.len() + cursor.0);
+                keep = line.len() + cursor.0 + 1;
+            }
+            let wrapped = row.wrapped;
+            let mut cells = row.cells;
+            if wrapped && row.padded && cells.last().is_some_and(|cell| cell.ch == ' ' && cell.width == 1) {
+                cells.pop();
+            }
+            line.extend(cells);
+            if !wrapped {
+                while line.len() > keep && line.last().is_some_and(is_trailing_blank) {
+                    line.pop();
+                }
+                lines.push(std::mem::take(&mut line));
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        let (mut rows, (mut cursor_y, cursor_x)) = rewrap(&lines, self.width, line_cursor);
+        // Rows go into the scrollback until the cursor reaches the top, what remains is cut from the bottom
+        if rows.len() > self.height {
+            let scrolled: Vec<Row> = rows.drain(..(rows.len() - self.height).min(cursor_y)).collect();
+            cursor_y -= scrolled.len();
+            let skip = scrolled.len().saturating_sub(self.scrollback_limit);
+            self.scrollback.extend(scrolled.into_iter().skip(skip));
+        }
+        rows.resize(self.height, Row::new(vec![], self.width));
+        (rows, (cursor_x.min(self.width - 1), cursor_y))
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.primary_lines.is_some()
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    // Returns the line shown at row y when the view is scrolled offset lines back into the history
+    pub fn view_line(&self, y: usize, offset: usize) -> &[CharacterCell] {
+        // The alternate screen has no scrollback
+        let offset = if self.is_alternate_screen() { 0 } else { offset.min(self.scrollback.len()) };
+        if y < offset {
+            &self.scrollback[self.scrollback.len() - offset + y]
+        } else {
+            &self.lines[y - offset]
+        }
+    }
+
+    fn blank_cell(&self) -> CharacterCell {
+        CharacterCell::new(' ', self.fg_color, self.bg_color, Attributes::empty())
+    }
+
+    fn move_cursor_to(&mut self, x: i64, y: i64) {
+        self.wrap_pending = false;
+        self.cursor.0 = x.max(0).min(self.width as i64 - 1) as usize;
+        self.cursor.1 = y.max(0).min(self.height as i64 - 1) as usize;
+    }
+
+    fn move_cursor_by(&mut self, dx: i64, dy: i64) {
+        self.move_cursor_to((self.cursor.0 as i64).saturating_add(dx), (self.cursor.1 as i64).saturating_add(dy));
+    }
+
+    // Relative vertical moves starting inside the scroll region stop at its margins
+    fn move_cursor_up(&mut self, count: i64) {
+        let top = if self.cursor.1 >= self.scroll_top { self.scroll_top } else { 0 };
+        let y = (self.cursor.1 as i64 - count).max(top as i64);
+        self.move_cursor_to(self.cursor.0 as i64, y);
+    }
+
+    fn move_cursor_down(&mut self, count: i64) {
+        let bottom = if self.cursor.1 <= self.scroll_bottom { self.scroll_bottom } else { self.height - 1 };
+        let y = (self.cursor.1 as i64 + count).min(bottom as i64);
+        self.move_cursor_to(self.cursor.0 as i64, y);
+    }
+
+    fn log_unhandled(&mut self, message: String) {
+        self.dev_console.push(message);
+    }
+
+    fn blank_line(&self) -> Row {
+        Row {
+            cells: vec![self.blank_cell(); self.width],
+            wrapped: false,
+            padded: false,
+        }
+    }
+
+    // A wide character that gets split at column x of line y, by writing over or moving one of its halves,
+    // is blanked entirely like xterm does, so no half is left without the other one
+    fn split_wide_char(&mut self, y: usize, x: usize) {
+        if x > 0 && x < self.width && self.lines[y][x].is_continuation() {
+            let blank = self.blank_cell();
+            self.lines[y][x - 1] = blank.clone();
+            self.lines[y][x] = blank;
+        }
+    }
+
+    // Blanks the cells of line y between start and end, end being exclusive
+    fn erase(&mut self, y: usize, start: usize, end: usize) {
+        let blank = self.blank_cell();
+        let end = end.min(self.width);
+        if start < end {
+            self.split_wide_char(y, start);
+            self.split_wide_char(y, end);
+            for cell in &mut self.lines[y][start..end] {
+                *cell = blank.clone();
+            }
+        }
+    }
+
+    // Only lines leaving the top of the screen go into the scrollback
+    fn scroll_up(&mut self, count: usize) {
+        let count = count.min(self.scroll_bottom + 1 - self.scroll_top);
+        for _ in 0..count {
+            let line = self.lines.remove(self.scroll_top);
+            self.lines.insert(self.scroll_bottom, self.blank_line());
+            if self.scroll_top == 0 && self.scrollback_limit > 0 && !self.is_alternate_screen() {
+                if self.scrollback.len() == self.scrollback_limit {
+                    self.scrollback.pop_front();
+                }
+                self.scrollback.push_back(line);
+                self.scrolled_lines += 1;
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        self.insert_lines(self.scroll_top, count);
+    }
+
+    // Inserts blank lines at y, pushing the lines below it out of the bottom of the scroll region
+    fn insert_lines(&mut self, y: usize, count: usize) {
+        if y < self.scroll_top || y > self.scroll_bottom {
+            return;
+        }
+        for _ in 0..count.min(self.scroll_bottom + 1 - y) {
+            self.lines.remove(self.scroll_bottom);
+            self.lines.insert(y, self.blank_line());
+        }
+    }
+
+    // Deletes lines at y, pulling the lines below it up and leaving blank lines at the bottom of the scroll region
+    fn delete_lines(&mut self, y: usize, count: usize) {
+        if y < self.scroll_top || y > self.scroll_bottom {
+            return;
+        }
+        for _ in 0..count.min(self.scroll_bottom + 1 - y) {
+            self.lines.remove(y);
+            self.lines.insert(self.scroll_bottom, self.blank_line());
+        }
+    }
+
+    fn insert_chars(&mut self, count: usize) {
+        let (x, y) = self.cursor;
+        let count = count.min(self.width - x);
+        // Blanks go in at x and the cells from width - count on fall off the end
+        self.split_wide_char(y, x);
+        self.split_wide_char(y, self.width - count);
+        let blank = self.blank_cell();
+        let line = &mut self.lines[y];
+        for _ in 0..count {
+            line.pop();
+            line.insert(x, blank.clone());
+        }
+    }
+
+    fn delete_chars(&mut self, count: usize) {
+        let (x, y) = self.cursor;
+        let count = count.min(self.width - x);
+        self.split_wide_char(y, x);
+        self.split_wide_char(y, x + count);
+        let blank = self.blank_cell();
+        let line = &mut self.lines[y];
+        for _ in 0..count {
+            line.remove(x);
+            line.push(blank.clone());
+        }
+    }
+
+    fn set_scroll_region(&mut self, params: &[i64]) {
+        let top = count_param(params, 0) as usize - 1;
+        let bottom = match params.get(1) {
+            Some(&p) if p > 0 => (p as usize).min(self.height) - 1,
+            _ => self.height - 1
+        };
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.move_cursor_to(0, 0);
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = SavedCursor {
+            position: self.cursor,
+            fg_color: self.fg_color,
+            bg_color: self.bg_color,
+            attrs: self.attrs,
+            charsets: self.charsets,
+            active_charset: self.active_charset,
+        };
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor;
+        self.move_cursor_to(saved.position.0 as i64, saved.position.1 as i64);
+        self.fg_color = saved.fg_color;
+        self.bg_color = saved.bg_color;
+        self.attrs = saved.attrs;
+        self.charsets = saved.charsets;
+        self.active_charset = saved.active_charset;
+    }
+
+    fn enter_alternate_screen(&mut self, clear: bool) {
+        if self.primary_lines.is_none() {
+            let alternate_lines = vec![self.blank_line(); self.height];
+            self.primary_lines = Some(std::mem::replace(&mut self.lines, alternate_lines));
+        } else if clear {
+            self.lines = vec![self.blank_line(); self.height];
+        }
+    }
+
+    fn leave_alternate_screen(&mut self, clear: bool) {
+        if clear && self.primary_lines.is_some() {
+            self.lines = vec![self.blank_line(); self.height];
+        }
+        if let Some(primary_lines) = self.primary_lines.take() {
+            self.lines = primary_lines;
+        }
+    }
+
+    // DECSET and DECRST
+    fn set_private_mode(&mut self, mode: i64, enabled: bool) {
+        match mode {
+            1 => self.application_cursor_keys = enabled,
+            7 => {
+                self.autowrap = enabled;
+                self.wrap_pending = false;
+            },
+            25 => self.cursor_visible = enabled,
+            47 => {
+                if enabled {
+                    self.enter_alternate_screen(false);
+                } else {
+                    self.leave_alternate_screen(false);
+                }
+            },
+            1047 => {
+                if enabled {
+                    self.enter_alternate_screen(false);
+                } else {
+                    self.leave_alternate_screen(true);
+                }
+            },
+            1048 => {
+                if enabled {
+                    self.save_cursor();
+                } else {
+                    self.restore_cursor();
+                }
+            },
+            1049 => {
+                if enabled {
+                    self.save_cursor();
+                    self.enter_alternate_screen(true);
+                } else {
+                    self.leave_alternate_screen(false);
+                    self.restore_cursor();
+                }
+            },
+            // Turning any of the tracking modes off stops the reports
+            9 | 1000 | 1002 | 1003 if !enabled => self.mouse_tracking = MouseTracking::Off,
+            9 => self.mouse_tracking = MouseTracking::Press,
+            1000 => self.mouse_tracking = MouseTracking::Click,
+            1002 => self.mouse_tracking = MouseTracking::ButtonMotion,
+            1003 => self.mouse_tracking = MouseTracking::AnyMotion,
+            2004 => self.bracketed_paste = enabled,
+            1006 | 1015 => {
+                let encoding = if mode == 1006 { MouseEncoding::Sgr } else { MouseEncoding::Urxvt };
+                if enabled {
+                    self.mouse_encoding = encoding;
+                } else if self.mouse_encoding == encoding {
+                    self.mouse_encoding = MouseEncoding::Default;
+                }
+            },
+            _ => {
+                self.log_unhandled(format!("Unhandled private mode {} {}", mode, if enabled { "set" } else { "reset" }));
+            }
+        }
+    }
+
+    // What DECRQM reports for a private mode, 1 when set, 2 when reset and 0 for modes it doesn't know
+    fn private_mode_state(&self, mode: i64) -> u8 {
+        let set = match mode {
+            1 => self.application_cursor_keys,
+            7 => self.autowrap,
+            25 => self.cursor_visible,
+            47 | 1047 | 1049 => self.is_alternate_screen(),
+            9 => self.mouse_tracking == MouseTracking::Press,
+            1000 => self.mouse_tracking == MouseTracking::Click,
+            1002 => self.mouse_tracking == MouseTracking::ButtonMotion,
+            1003 => self.mouse_tracking == MouseTracking::AnyMotion,
+            1006 => self.mouse_encoding == MouseEncoding::Sgr,
+            1015 => self.mouse_encoding == MouseEncoding::Urxvt,
+            2004 => self.bracketed_paste,
+            _ => return 0
+        };
+        if set { 1 } else { 2 }
+    }
+
+    // Device attributes, status reports, DECRQM and XTVERSION, returns false for anything else
+    fn answer_query(&mut self, params: &[i64], intermediates: &[u8], action: char) -> bool {
+        let response = match (intermediates, action, params[0]) {
+            // A VT220 with ANSI colors
+            (b"", 'c', 0) => "\x1B[?62;22c".to_string(),
+            (b">", 'c', 0) => format!("\x1B[>1;{};0c", version_number()),
+            (b"", 'n', 5) => "\x1B[0n".to_string(),
+            (b"", 'n', 6) => format!("\x1B[{};{}R", self.cursor.1 + 1, self.cursor.0 + 1),
+            (b"?", 'n', 6) => format!("\x1B[?{};{}R", self.cursor.1 + 1, self.cursor.0 + 1),
+            // None of the ANSI modes are supported
+            (b"$", 'p', mode) => format!("\x1B[{};0$y", mode),
+            (b"?$", 'p', mode) => format!("\x1B[?{};{}$y", mode, self.private_mode_state(mode)),
+            (b">", 'q', 0) => format!("\x1BP>|TermUI {}\x1B\\", env!("CARGO_PKG_VERSION")),
+            _ => return false
+        };
+        self.responses.extend_from_slice(response.as_bytes());
+        true
+    }
+
+    // Moves to the next tab stop, or the last column when there is none
+    fn tab_forward(&mut self, count: i64) {
+        let mut x = self.cursor.0;
+        // Every step moves at least one column until the last one, so more steps than columns change nothing
+        for _ in 0..count.min(self.width as i64) {
+            x = (x + 1..self.width).find(|&x| self.tab_stops[x]).unwrap_or(self.width - 1);
+        }
+        self.move_cursor_to(x as i64, self.cursor.1 as i64);
+    }
+
+    // Moves to the previous tab stop, or the first column when there is none
+    fn tab_backward(&mut self, count: i64) {
+        let mut x = self.cursor.0;
+        for _ in 0..count.min(self.width as i64) {
+            x = (0..x).rev().find(|&x| self.tab_stops[x]).unwrap_or(0);
+        }
+        self.move_cursor_to(x as i64, self.cursor.1 as i64);
+    }
+
+    // Zero width characters belong to the character printed before them
+    fn attach_combining(&mut self, c: char) {
+        let (x, y) = self.cursor;
+        let mut x = if self.wrap_pending {
+            x
+        } else if x > 0 {
+            x - 1
+        } else {
+            return;
+        };
+        if self.lines[y][x].is_continuation() && x > 0 {
+            x -= 1;
+        }
+        let cell = &mut self.lines[y][x];
+        if cell.combining.len() < MAX_COMBINING_CHARS {
+            cell.combining.push(c);
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.1 == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.1 + 1 < self.height {
+            self.cursor.1 += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.1 == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor.1 > 0 {
+            self.cursor.1 -= 1;
+        }
+    }
+
+    // Prints a character that already went through the charset mapping
+    fn print_char(&mut self, c: char) {
+        self.printed_chars += 1;
+        let width = c.width().unwrap_or(1);
+        if width == 0 {
+            self.attach_combining(c);
+            return;
+        }
+        if width > self.width {
+            return;
+        }
+        self.last_printed = Some(c);
+
+        // A wide character that doesn't fit at the end of the line goes on the next one
+        let mut padded = false;
+        if width == 2 && self.autowrap && !self.wrap_pending && self.cursor.0 + 1 >= self.width {
+            let (x, y) = self.cursor;
+            self.erase(y, x, x + 1);
+            self.wrap_pending = true;
+            padded = true;
+        }
+        if self.wrap_pending {
+            self.lines[self.cursor.1].wrapped = true;
+            self.lines[self.cursor.1].padded = padded;
+            self.cursor.0 = 0;
+            self.line_feed();
+        }
+
+        let (x, y) = (self.cursor.0.min(self.width - width), self.cursor.1);
+        self.split_wide_char(y, x);
+        self.split_wide_char(y, x + width);
+        self.lines[y][x] = CharacterCell::new(c, self.fg_color, self.bg_color, self.attrs).with_width(width as u8);
+        if width == 2 {
+            self.lines[y][x + 1] = CharacterCell::new(' ', self.fg_color, self.bg_color, self.attrs).with_width(0);
+        }
+
+        if x + width < self.width {
+            self.cursor.0 = x + width;
+        } else {
+            self.cursor.0 = self.width - 1;
+            if self.autowrap {
+                self.wrap_pending = true;
+            }
+        }
+    }
+}
+
+impl Perform for EmbedGrid {
+    fn print(&mut self, c: char) {
+        let c = self.charsets[self.active_charset].map(c);
+        self.print_char(c);
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.move_cursor_by(-1, 0);
+            },
+            0x09 => {
+                self.tab_forward(1);
+            },
+            0x0A..=0x0C => { // LF, VT and FF all move down a line
+                self.line_feed();
+            }
+            0x0D => {
+                self.move_cursor_to(0, self.cursor.1 as i64);
+            }
+            0x07 => {
+                print!("\x07");
+            }
+            0x0E => self.active_charset = 1, // Shift out to G1
+            0x0F => self.active_charset = 0, // Shift in to G0
+            0x00 => {}
+            c => {
+                self.log_unhandled(format!("Unhandled control character {:#04x}", c));
+            }
+        };
+    }
+
+    fn hook(&mut self, params: &[i64], intermediates: &[u8], _ignore: bool, action: char) {
+        self.log_unhandled(format!("Unhandled DCS {:?} {:?} {:?}", params, String::from_utf8_lossy(intermediates), action));
+    }
+
+    // The DCS payload is dropped, hook already logged the sequence
+    fn put(&mut self, _byte: u8) {}
+
+    fn unhook(&mut self) {}
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        match params {
+            [b"0", text @ ..] => {
+                self.title = osc_text(text);
+                self.icon_name = osc_text(text);
+            },
+            [b"1", text @ ..] => self.icon_name = osc_text(text),
+            [b"2", text @ ..] => self.title = osc_text(text),
+            [b"7", url] => {
+                match parse_file_url(url) {
+                    Some(cwd) => self.cwd = Some(cwd),
+                    None => self.log_unhandled(format!("Unhandled OSC 7 URL {:?}", String::from_utf8_lossy(url)))
+                }
+            },
+            // The selection parameter is kept for the answer, every selection shares the same clipboard
+            [b"52", selection, b"?"] => {
+                let selection = String::from_utf8_lossy(selection).into_owned();
+                self.clipboard_requests.push(ClipboardRequest::Query(selection, bell_terminated));
+            },
+            [b"52", _, data] => {
+                match base64::decode(data) {
+                    Ok(text) => self.clipboard_requests.push(ClipboardRequest::Set(String::from_utf8_lossy(&text).into_owned())),
+                    Err(e) => self.log_unhandled(format!("Invalid OSC 52 data: {}", e))
+                }
+            },
+            _ => {
+                let params: Vec<_> = params.iter().map(|p| String::from_utf8_lossy(p)).collect();
+                self.log_unhandled(format!("Unhandled OSC {:?}", params));
+            }
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, action: char) {
+        if !ignore && intermediates == b"?" && (action == 'h' || action == 'l') {
+            for &mode in params {
+                self.set_private_mode(mode, action == 'h');
+            }
+            return;
+        }
+        if !ignore && self.answer_query(params, intermediates, action) {
+            return;
+        }
+        if ignore || !intermediates.is_empty() {
+            self.log_unhandled(format!("Unhandled CSI {:?} {:?} {:?}", params, String::from_utf8_lossy(intermediates), action));
+            return;
+        }
+
+        match action {
+            'A' => { // Cursor Up
+                self.move_cursor_up(count_param(params, 0));
+            },
+            'B' => { // Cursor Down
+                self.move_cursor_down(count_param(params, 0));
+            },
+            'C' => { // Cursor Forwards
+                self.move_cursor_by(count_param(params, 0), 0);
+            },
+            'D' => { // Cursor Back
+                self.move_cursor_by(-count_param(params, 0), 0);
+            },
+            'E' => { // Go down then to the beginning of the line
+                self.move_cursor_down(count_param(params, 0));
+                self.cursor.0 = 0;
+            },
+            'F' => { // Go up then to the beginning of the line
+                self.move_cursor_up(count_param(params, 0));
+                self.cursor.0 = 0;
+            },
+            'G' | '`' => { // Set cursor horizontal pos
+                self.move_cursor_to(count_param(params, 0) - 1, self.cursor.1 as i64);
+            },
+            'd' => { // Set cursor vertical pos
+                self.move_cursor_to(self.cursor.0 as i64, count_param(params, 0) - 1);
+            },
+            'H' | 'f' => { // Set cursor pos
+                self.move_cursor_to(count_param(params, 1) - 1, count_param(params, 0) - 1);
+            },
+            'J' => { // Erase in display
+                let (x, y) = self.cursor;
+                match mode_param(params) {
+                    0 => {
+                        self.erase(y, x, self.width);
+                        for line in (y + 1)..self.height {
+                            self.erase(line, 0, self.width);
+                        }
+                    },
+                    1 => {
+                        for line in 0..y {
+                            self.erase(line, 0, self.width);
+                        }
+                        self.erase(y, 0, x + 1);
+                    },
+                    2 => {
+                        for line in 0..self.height {
+                            self.erase(line, 0, self.width);
+                        }
+                    },
+                    3 => { // Erase the scrollback
+                        self.scrollback.clear();
+                    },
+                    _ => {}
+                }
+            },
+            'K' => { // Erase in line
+                let (x, y) = self.cursor;
+                match mode_param(params) {
+                    0 => self.erase(y, x, self.width),
+                    1 => self.erase(y, 0, x + 1),
+                    2 => self.erase(y, 0, self.width),
+                    _ => {}
+                }
+            },
+            'X' => { // Erase characters
+                let (x, y) = self.cursor;
+                self.erase(y, x, x + count_param(params, 0) as usize);
+            },
+            'I' => { // Cursor forward tabulation
+                self.tab_forward(count_param(params, 0));
+            },
+            'Z' => { // Cursor backward tabulation
+                self.tab_backward(count_param(params, 0));
+            },
+            'g' => { // Tab clear
+                match mode_param(params) {
+                    0 => self.tab_stops[self.cursor.0] = false,
+                    3 => self.tab_stops.iter_mut().for_each(|stop| *stop = false),
+                    _ => {}
+                }
+            },
+            'L' => { // Insert lines
+                self.insert_lines(self.cursor.1, count_param(params, 0) as usize);
+                self.move_cursor_to(0, self.cursor.1 as i64);
+            },
+            'M' => { // Delete lines
+                self.delete_lines(self.cursor.1, count_param(params, 0) as usize);
+                self.move_cursor_to(0, self.cursor.1 as i64);
+            },
+            '@' => { // Insert characters
+                self.insert_chars(count_param(params, 0) as usize);
+            },
+            'P' => { // Delete characters
+                self.delete_chars(count_param(params, 0) as usize);
+            },
+            'b' => { // Repeat the last printed character, ncurses uses it for runs of the same character
+                if let Some(c) = self.last_printed {
+                    for _ in 0..count_param(params, 0) {
+                        self.print_char(c);
+                    }
+                }
+            },
+            'S' => { // Scroll up
+                self.scroll_up(count_param(params, 0) as usize);
+            },
+            'T' => { // Scroll down
+                self.scroll_down(count_param(params, 0) as usize);
+            },
+            'r' => { // Set scroll region
+                self.set_scroll_region(params);
+            },
+            's' => { // Save cursor
+                self.save_cursor();
+            },
+            'u' => { // Restore cursor
+                self.restore_cursor();
+            },
+            'm' => { // Select Graphic Rendition
+                let mut params = params.iter();
+                while let Some(&param) = params.next() {
+                    match param {
+                        0 => { // Reset all
+                            self.fg_color = Color::Default;
+                            self.bg_color = Color::Default;
+                            self.attrs = Attributes::empty();
+                        },
+                        30..=37 => self.fg_color = Color::Ansi((param - 30) as u8),
+                        38 => { // Extended FG color
+                            if let Some(color) = Color::from_sgr_params(&mut params) {
+                                self.fg_color = color;
+                            }
+                        },
+                        39 => self.fg_color = Color::Default,
+                        40..=47 => self.bg_color = Color::Ansi((param - 40) as u8),
+                        48 => { // Extended BG color
+                            if let Some(color) = Color::from_sgr_params(&mut params) {
+                                self.bg_color = color;
+                            }
+                        },
+                        49 => self.bg_color = Color::Default,
+                        90..=97 => self.fg_color = Color::Ansi((param - 90 + 8) as u8),
+                        100..=107 => self.bg_color = Color::Ansi((param - 100 + 8) as u8),
+                        _ => {
+                            self.attrs.apply_sgr(param);
+                        }
+                    }
+                }
+            },
+            _ => {
+                self.log_unhandled(format!("Unhandled CSI {:?} {:?}", params, action));
+            }
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        match byte {
+            b'\\' => {} // String terminator, the string itself was already dispatched
+            b'D' if intermediates.is_empty() => self.line_feed(), // Index
+            b'E' if intermediates.is_empty() => { // Next line
+                self.cursor.0 = 0;
+                self.line_feed();
+            },
+            b'M' if intermediates.is_empty() => self.reverse_index(),
+            b'H' if intermediates.is_empty() => self.tab_stops[self.cursor.0] = true, // Tab set
+            b'7' if intermediates.is_empty() => self.save_cursor(),
+            b'8' if intermediates.is_empty() => self.restore_cursor(),
+            // DECKPAM and DECKPNM, the host terminal reports keypad keys like the main ones so both modes send the same
+            b'=' | b'>' if intermediates.is_empty() => {}
+            // Designates G0 or G1, ncurses switches to line drawing this way
+            _ if intermediates == b"(" || intermediates == b")" => {
+                let index = if intermediates == b"(" { 0 } else { 1 };
+                match Charset::from_designator(byte) {
+                    Some(charset) => self.charsets[index] = charset,
+                    None => self.log_unhandled(format!("Unhandled charset {:?}", byte as char))
+                }
+            },
+            _ => {
+                self.log_unhandled(format!("Unhandled ESC {:?} {:?}", String::from_utf8_lossy(intermediates), byte as char));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vte::Parser;
+    use super::{ClipboardRequest, Color, EmbedGrid, MouseEncoding, MouseTracking};
+
+    fn grid_with(width: usize, height: usize, input: &str) -> EmbedGrid {
+        let mut grid = EmbedGrid::new(width, height, 100);
+        feed(&mut grid, input);
+        grid
+    }
+
+    fn feed(grid: &mut EmbedGrid, input: &str) {
+        let mut parser = Parser::new();
+        for byte in input.bytes() {
+            parser.advance(grid, byte);
+        }
+    }
+
+    // The text of a row as shown with the view scrolled back by offset, without trailing blanks
+    fn row_text(grid: &EmbedGrid, y: usize, offset: usize) -> String {
+        let text: String = grid.view_line(y, offset).iter()
+            .filter(|cell| !cell.is_continuation())
+            .map(|cell| cell.ch)
+            .collect();
+        text.trim_end().to_string()
+    }
+
+    fn screen_text(grid: &EmbedGrid) -> Vec<String> {
+        (0..grid.height).map(|y| row_text(grid, y, 0)).collect()
+    }
+
+    #[test]
+    fn huge_parameters_are_clamped() {
+        let mut grid = grid_with(10, 5, "ab\x1B[9223372036854775807C");
+        assert_eq!(grid.cursor, (9, 0));
+        feed(&mut grid, "\x1B[9223372036854775807D\x1B[9223372036854775807B");
+        assert_eq!(grid.cursor, (0, 4));
+        feed(&mut grid, "\x1B[9223372036854775807A\x1B[9223372036854775807;9223372036854775807H");
+        assert_eq!(grid.cursor, (9, 4));
+        feed(&mut grid, "\x1B[9223372036854775807X\x1B[9223372036854775807L\x1B[9223372036854775807P");
+        // Inserting lines moves the cursor to the first column
+        assert_eq!(grid.cursor, (0, 4));
+    }
+
+    #[test]
+    fn huge_tab_counts_stop_at_the_edges() {
+        let mut grid = grid_with(80, 5, "\x1B[3000000000I");
+        assert_eq!(grid.cursor, (79, 0));
+        feed(&mut grid, "\x1B[3000000000Z");
+        assert_eq!(grid.cursor, (0, 0));
+        feed(&mut grid, "\x1B[2I");
+        assert_eq!(grid.cursor, (16, 0));
+    }
+
+    #[test]
+    fn vertical_moves_stop_at_the_scroll_margins() {
+        // Region from the third to the seventh row, DECSTBM homes the cursor
+        let mut grid = grid_with(10, 10, "\x1B[3;7r\x1B[5H\x1B[20A");
+        assert_eq!(grid.cursor, (0, 2));
+        feed(&mut grid, "\x1B[20B");
+        assert_eq!(grid.cursor, (0, 6));
+        feed(&mut grid, "\x1B[3E");
+        assert_eq!(grid.cursor, (0, 6));
+        // Outside of the region the screen edges apply
+        feed(&mut grid, "\x1B[9H\x1B[20B");
+        assert_eq!(grid.cursor, (0, 9));
+        feed(&mut grid, "\x1B[1H\x1B[20B");
+        assert_eq!(grid.cursor, (0, 6));
+        feed(&mut grid, "\x1B[2H\x1B[20A");
+        assert_eq!(grid.cursor, (0, 0));
+    }
+
+    #[test]
+    fn setting_the_scroll_region_cancels_a_pending_wrap() {
+        let grid = grid_with(5, 3, "abcde\x1B[rX");
+        assert_eq!(screen_text(&grid), ["Xbcde", "", ""]);
+        assert_eq!(grid.cursor, (1, 0));
+    }
+
+    #[test]
+    fn reflow_round_trips_when_shrinking_and_growing() {
+        let mut grid = grid_with(10, 5, "0123456789abcdef\r\nxyz");
+        grid.resize(4, 5);
+        assert_eq!(screen_text(&grid), ["0123", "4567", "89ab", "cdef", "xyz"]);
+        assert_eq!(grid.cursor, (3, 4));
+        grid.resize(10, 5);
+        assert_eq!(screen_text(&grid), ["0123456789", "abcdef", "xyz", "", ""]);
+        assert_eq!(grid.cursor, (3, 2));
+        assert_eq!(grid.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn reflow_keeps_the_cursor_on_the_same_character() {
+        let mut grid = grid_with(20, 5, "hello world\x1B[7G");
+        grid.resize(4, 5);
+        assert_eq!(screen_text(&grid), ["hell", "o wo", "rld", "", ""]);
+        assert_eq!(grid.cursor, (2, 1));
+        assert_eq!(grid.view_line(1, 0)[2].ch, 'w');
+    }
+
+    #[test]
+    fn reflow_drops_the_padding_left_by_wide_characters() {
+        let mut grid = grid_with(5, 3, "abcd漢字");
+        assert_eq!(screen_text(&grid), ["abcd", "漢字", ""]);
+        grid.resize(20, 3);
+        assert_eq!(screen_text(&grid), ["abcd漢字", "", ""]);
+        // Padding added by rewrapping goes away again too
+        grid.resize(5, 3);
+        grid.resize(20, 3);
+        assert_eq!(screen_text(&grid), ["abcd漢字", "", ""]);
+    }
+
+    // Every wide cell must be followed by its continuation and every continuation preceded by its wide cell
+    fn assert_wide_chars_whole(grid: &EmbedGrid) {
+        for y in 0..grid.height {
+            let line = grid.view_line(y, 0);
+            for x in 0..line.len() {
+                assert_eq!(line[x].is_wide(), x + 1 < line.len() && line[x + 1].is_continuation(), "row {} column {}", y, x);
+            }
+        }
+    }
+
+    #[test]
+    fn writing_over_half_a_wide_character_blanks_it() {
+        let mut grid = grid_with(12, 3, "漢字\x1B[1;2Ha");
+        assert_wide_chars_whole(&grid);
+        assert_eq!(row_text(&grid, 0, 0), " a字");
+        grid.resize(12, 3);
+        assert_eq!(row_text(&grid, 0, 0), " a字");
+        assert_eq!(grid.view_line(0, 0)[1].ch, 'a');
+        // Erasing, inserting and deleting can split them too
+        let grid = grid_with(12, 3, "漢字漢字\x1B[1;4H\x1B[1X\r\n漢字漢字\x1B[2;2H\x1B[@\r\n漢字漢字\x1B[3;2H\x1B[2P");
+        assert_wide_chars_whole(&grid);
+        assert_eq!(screen_text(&grid), ["漢  漢字", "   字漢字", "  漢字"]);
+        // Inserting pushes the last character half off the end of the line
+        let grid = grid_with(5, 1, "a漢字\x1B[1;1H\x1B[@");
+        assert_wide_chars_whole(&grid);
+        assert_eq!(screen_text(&grid), [" a漢"]);
+    }
+
+    #[test]
+    fn reflow_respects_the_scrollback_limit() {
+        let mut grid = EmbedGrid::new(10, 3, 2);
+        feed(&mut grid, "1\r\n2\r\n3\r\n4\r\n5");
+        assert_eq!(grid.scrollback_len(), 2);
+        grid.resize(10, 2);
+        assert_eq!(grid.scrollback_len(), 2);
+        assert_eq!([row_text(&grid, 0, 2), row_text(&grid, 1, 2)], ["2", "3"]);
+        assert_eq!(screen_text(&grid), ["4", "5"]);
+        assert_eq!(grid.cursor, (1, 1));
+    }
+
+    #[test]
+    fn reflow_leaves_the_alternate_screen_alone() {
+        let mut grid = grid_with(10, 5, "abcdefghijkl\x1B[?1049h\x1B[Halt screen");
+        grid.resize(6, 5);
+        // The alternate screen is only cropped, the primary one gets reflowed behind it
+        assert_eq!(screen_text(&grid), ["alt sc", "", "", "", ""]);
+        feed(&mut grid, "\x1B[?1049l");
+        assert_eq!(screen_text(&grid), ["abcdef", "ghijkl", "", "", ""]);
+        assert_eq!(grid.cursor, (0, 2));
+    }
+
+    #[test]
+    fn line_drawing_through_the_dec_special_graphics_set() {
+        // What ncurses sends with TERM=xterm-256color for a box corner and its attribute reset
+        let mut grid = grid_with(10, 3, "\x1B(0lqk\x1B(B\x1B[mlqk");
+        assert!(grid.dev_console.is_empty());
+        assert_eq!(row_text(&grid, 0, 0), "┌─┐lqk");
+        // G1 through shift out and shift in
+        feed(&mut grid, "\r\n\x1B)0x\x0Ex\x0Fx");
+        assert_eq!(row_text(&grid, 1, 0), "x│x");
+        // DECSC and DECRC keep the charsets
+        feed(&mut grid, "\x1B(0\x1B7\x1B(B\x1B8\r\nq");
+        assert_eq!(row_text(&grid, 2, 0), "─");
+    }
+
+    #[test]
+    fn repeat_the_last_printed_character() {
+        // What ncurses sends with TERM=xterm-256color for a horizontal box line
+        let mut grid = grid_with(10, 3, "\x1B(0lq\x1B[4bk\x1B(B");
+        assert_eq!(row_text(&grid, 0, 0), "┌─────┐");
+        // Repeats wrap like printing does
+        feed(&mut grid, "\r\nab\x1B[9b");
+        assert_eq!(screen_text(&grid), ["┌─────┐", "abbbbbbbbb", "b"]);
+    }
+
+    #[test]
+    fn reset_modes_undoes_what_a_program_set_up() {
+        let mut grid = grid_with(10, 5, "primary\x1B[?1049h\x1B[?1h\x1B[?1002h\x1B[?1006h\x1B[?2004h\x1B[?25l\x1B[?7l\x1B[2;3r\x1B[1;31m\x1B(0\x1B[3g");
+        grid.reset_modes();
+        assert_eq!(screen_text(&grid)[0], "primary");
+        assert!(!grid.application_cursor_keys);
+        assert!(grid.mouse_tracking == MouseTracking::Off);
+        assert!(grid.mouse_encoding == MouseEncoding::Default);
+        assert!(!grid.bracketed_paste);
+        assert!(grid.cursor_visible);
+        assert!(grid.autowrap);
+        assert_eq!((grid.scroll_top, grid.scroll_bottom), (0, 4));
+        assert!(grid.fg_color == Color::Default && grid.attrs.is_empty());
+        feed(&mut grid, "\r\nq\tx");
+        assert_eq!(row_text(&grid, 1, 0), "q       x");
+    }
+
+    #[test]
+    fn long_clipboard_copies_are_not_truncated() {
+        let text = "0123456789abcdef".repeat(1024);
+        let grid = grid_with(10, 3, &format!("\x1B]52;c;{}\x07", base64::encode(&text)));
+        match grid.clipboard_requests.as_slice() {
+            [ClipboardRequest::Set(copied)] => assert_eq!(copied, &text),
+            _ => panic!("expected a single copy")
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+
+// The modifier parameter xterm adds to function and cursor keys, 1 meaning no modifier
+fn modifier_param(modifiers: KeyModifiers) -> u8 {
+    let mut param = 1;
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        param += 1;
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        param += 2;
+    }
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        param += 4;
+    }
+    param
+}
+
+// What Ctrl turns a character into, None for characters it doesn't change
+fn control_char(ch: char) -> Option<u8> {
+    match ch {
+        'a'..='z' => Some(ch as u8 - b'a' + 1),
+        'A'..='Z' => Some(ch as u8 - b'A' + 1),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1B),
+        '\\' | '4' => Some(0x1C),
+        ']' | '5' => Some(0x1D),
+        '^' | '6' => Some(0x1E),
+        '_' | '/' | '7' => Some(0x1F),
+        '?' | '8' => Some(0x7F),
+        _ => None
+    }
+}
+
+// Cursor keys and Home/End, sent with SS3 in application cursor keys mode unless modified
+fn cursor_key(letter: char, modifiers: KeyModifiers, application_cursor_keys: bool) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 if application_cursor_keys => format!("\x1BO{}", letter).into_bytes(),
+        1 => format!("\x1B[{}", letter).into_bytes(),
+        param => format!("\x1B[1;{}{}", param, letter).into_bytes()
+    }
+}
+
+// Keys sent as CSI number ~
+fn tilde_key(number: u8, modifiers: KeyModifiers) -> Vec<u8> {
+    match modifier_param(modifiers) {
+        1 => format!("\x1B[{}~", number).into_bytes(),
+        param => format!("\x1B[{};{}~", number, param).into_bytes()
+    }
+}
+
+// Encodes a key the way xterm does, an empty result means the key has no encoding
+pub fn encode_key(code: KeyCode, modifiers: KeyModifiers, application_cursor_keys: bool) -> Vec<u8> {
+    // Alt prefixes the plain keys with ESC
+    let alt_prefixed = |bytes: &[u8]| {
+        let mut encoded = vec![];
+        if modifiers.contains(KeyModifiers::ALT) {
+            encoded.push(0x1B);
+        }
+        encoded.extend_from_slice(bytes);
+        encoded
+    };
+    match code {
+        KeyCode::Char(ch) => {
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                if let Some(byte) = control_char(ch) {
+                    return alt_prefixed(&[byte]);
+                }
+            }
+            let mut buf = [0; 4];
+            alt_prefixed(ch.encode_utf8(&mut buf).as_bytes())
+        },
+        KeyCode::Enter => alt_prefixed(b"\r"),
+        KeyCode::Tab => alt_prefixed(b"\t"),
+        KeyCode::BackTab => b"\x1B[Z".to_vec(),
+        KeyCode::Backspace => {
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                alt_prefixed(&[0x08])
+            } else {
+                alt_prefixed(&[0x7F])
+            }
+        },
+        KeyCode::Esc => alt_prefixed(b"\x1B"),
+        KeyCode::Null => alt_prefixed(&[0x00]),
+        KeyCode::Up => cursor_key('A', modifiers, application_cursor_keys),
+        KeyCode::Down => cursor_key('B', modifiers, application_cursor_keys),
+        KeyCode::Right => cursor_key('C', modifiers, application_cursor_keys),
+        KeyCode::Left => cursor_key('D', modifiers, application_cursor_keys),
+        KeyCode::Home => cursor_key('H', modifiers, application_cursor_keys),
+        KeyCode::End => cursor_key('F', modifiers, application_cursor_keys),
+        KeyCode::Insert => tilde_key(2, modifiers),
+        KeyCode::Delete => tilde_key(3, modifiers),
+        KeyCode::PageUp => tilde_key(5, modifiers),
+        KeyCode::PageDown => tilde_key(6, modifiers),
+        KeyCode::F(n @ 1..=4) => {
+            let letter = (b'P' + n - 1) as char;
+            match modifier_param(modifiers) {
+                1 => format!("\x1BO{}", letter).into_bytes(),
+                param => format!("\x1B[1;{}{}", param, letter).into_bytes()
+            }
+        },
+        KeyCode::F(n @ 5..=12) => {
+            let number = [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5];
+            tilde_key(number, modifiers)
+        },
+        // Lone modifiers, media keys and the like
+        _ => vec![]
+    }
+}
+
+// Pasted text goes in as it is with line breaks turned into carriage returns like Enter sends,
+// wrapped in markers when the program asked for bracketed paste
+pub fn encode_paste(text: &str, bracketed_paste: bool) -> Vec<u8> {
+    let text = text.replace("\r\n", "\r").replace('\n', "\r");
+    if bracketed_paste {
+        // The text must not be able to end the paste early
+        format!("\x1B[200~{}\x1B[201~", text.replace("\x1B[201~", "")).into_bytes()
+    } else {
+        text.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use super::{encode_key, encode_paste};
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> Vec<u8> {
+        encode_key(code, modifiers, false)
+    }
+
+    #[test]
+    fn cursor_keys_follow_application_cursor_keys_mode() {
+        assert_eq!(encode_key(KeyCode::Up, KeyModifiers::NONE, false), b"\x1B[A");
+        assert_eq!(encode_key(KeyCode::Up, KeyModifiers::NONE, true), b"\x1BOA");
+        assert_eq!(encode_key(KeyCode::Home, KeyModifiers::NONE, true), b"\x1BOH");
+        // Modified cursor keys are always sent with CSI
+        assert_eq!(encode_key(KeyCode::Left, KeyModifiers::CONTROL, true), b"\x1B[1;5D");
+        assert_eq!(encode_key(KeyCode::End, KeyModifiers::SHIFT | KeyModifiers::ALT, false), b"\x1B[1;4F");
+    }
+
+    #[test]
+    fn control_and_alt_characters() {
+        assert_eq!(key(KeyCode::Char('c'), KeyModifiers::CONTROL), [0x03]);
+        assert_eq!(key(KeyCode::Char('C'), KeyModifiers::CONTROL | KeyModifiers::SHIFT), [0x03]);
+        assert_eq!(key(KeyCode::Char(' '), KeyModifiers::CONTROL), [0x00]);
+        assert_eq!(key(KeyCode::Char('['), KeyModifiers::CONTROL), [0x1B]);
+        assert_eq!(key(KeyCode::Char('x'), KeyModifiers::ALT), b"\x1Bx");
+        assert_eq!(key(KeyCode::Char('a'), KeyModifiers::CONTROL | KeyModifiers::ALT), [0x1B, 0x01]);
+        // Characters Ctrl doesn't change go through as they are
+        assert_eq!(key(KeyCode::Char('é'), KeyModifiers::CONTROL), "é".as_bytes());
+        assert_eq!(key(KeyCode::Char('日'), KeyModifiers::NONE), "日".as_bytes());
+    }
+
+    #[test]
+    fn editing_keys() {
+        assert_eq!(key(KeyCode::Enter, KeyModifiers::NONE), b"\r");
+        assert_eq!(key(KeyCode::Backspace, KeyModifiers::NONE), [0x7F]);
+        assert_eq!(key(KeyCode::Backspace, KeyModifiers::CONTROL), [0x08]);
+        assert_eq!(key(KeyCode::Backspace, KeyModifiers::ALT), [0x1B, 0x7F]);
+        assert_eq!(key(KeyCode::BackTab, KeyModifiers::SHIFT), b"\x1B[Z");
+        assert_eq!(key(KeyCode::Delete, KeyModifiers::NONE), b"\x1B[3~");
+        assert_eq!(key(KeyCode::PageUp, KeyModifiers::CONTROL), b"\x1B[5;5~");
+    }
+
+    #[test]
+    fn function_keys() {
+        assert_eq!(key(KeyCode::F(1), KeyModifiers::NONE), b"\x1BOP");
+        assert_eq!(key(KeyCode::F(4), KeyModifiers::SHIFT), b"\x1B[1;2S");
+        assert_eq!(key(KeyCode::F(5), KeyModifiers::NONE), b"\x1B[15~");
+        assert_eq!(key(KeyCode::F(12), KeyModifiers::ALT), b"\x1B[24;3~");
+        assert!(key(KeyCode::F(13), KeyModifiers::NONE).is_empty());
+    }
+
+    #[test]
+    fn pastes_are_bracketed_when_asked_for() {
+        assert_eq!(encode_paste("a\r\nb\nc", false), b"a\rb\rc");
+        assert_eq!(encode_paste("ls", true), b"\x1B[200~ls\x1B[201~");
+        // An end marker inside the text can't end the paste early
+        assert_eq!(encode_paste("a\x1B[201~b", true), b"\x1B[200~ab\x1B[201~");
+    }
+}
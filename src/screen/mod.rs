@@ -1,53 +1,79 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::ops::Deref;
-use std::borrow::BorrowMut;
-use std::io::*;
-use std::process::{Command, Stdio, ChildStdin, ChildStdout, exit};
-use std::thread;
-use std::sync::Arc;
-use crossbeam::queue::SegQueue;
-use std::str;
-use crossterm::event::{KeyCode, KeyModifiers};
-
-use std::path::Path;
-use nix::fcntl::{OFlag, open};
-use nix::pty::{grantpt, posix_openpt, ptsname, unlockpt, Winsize};
-use nix::sys::stat::Mode;
-use nix::unistd::{fork, ForkResult, close, setsid, dup2, Pid};
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
-use nix::{ioctl_none_bad, ioctl_write_ptr_bad};
-
-use libc;
-use std::os::raw::{c_char, c_void};
-use std::ffi::{CString, CStr};
-use core::ptr;
-use std::fs::File;
-use libc::{TIOCSCTTY, TIOCSWINSZ};
-use term::Attr;
-use vte::{Parser, Perform};
-use std::alloc::handle_alloc_error;
-use std::thread::current;
-
-ioctl_write_ptr_bad!(set_window_size, TIOCSWINSZ, Winsize);
-ioctl_none_bad!(set_controlling_terminal, TIOCSCTTY);
-
-#[derive(Copy, Clone)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use std::ffi::CString;
+use crossbeam::channel::Sender;
+use crossterm::event::{KeyCode, KeyModifiers, MouseEvent, MouseEventKind};
+use nix::sys::signal::Signal;
+use crate::config::{Config, ExitPolicy};
+use crate::renderer::CellBuffer;
+use vte::Parser;
+
+mod attributes;
+mod charset;
+mod child;
+pub mod clipboard;
+mod color;
+mod command;
+mod grid;
+mod keys;
+mod mouse;
+
+pub use attributes::Attributes;
+pub use clipboard::Clipboard;
+pub use color::Color;
+pub use command::Command;
+use child::{Child, ChildEvent, ExitStatus};
+use clipboard::ClipboardRequest;
+use grid::EmbedGrid;
+use mouse::MouseTracking;
+
+static NEXT_WINDOW_ID: AtomicUsize = AtomicUsize::new(1);
+
+// How much output a window parses at once, so that a program writing without pause can't starve input and drawing
+const OUTPUT_BUDGET: usize = 64 * 1024;
+
+// Windows started less than this long ago are not restarted, so a failing command doesn't run in a loop
+const RESTART_MIN_UPTIME: Duration = Duration::from_secs(1);
+
+#[derive(Clone, PartialEq)]
 pub struct CharacterCell {
     pub ch: char,
-    pub fg: u8,
-    pub bg: u8,
-    pub attrs: Attr,
+    // Zero width characters drawn along with ch, like combining accents
+    pub combining: Vec<char>,
+    // Wide characters take 2 columns, the cell after them is a continuation of width 0
+    pub width: u8,
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: Attributes,
 }
 
-struct EmbedGrid {
-    printed_chars: usize,
-    cursor: (usize, usize),
-    grid: Vec<CharacterCell>,
-    fg_color: u8,
-    bg_color: u8,
-    width: usize,
-    height: usize
+impl CharacterCell {
+    pub fn new(ch: char, fg: Color, bg: Color, attrs: Attributes) -> CharacterCell {
+        CharacterCell {
+            ch,
+            combining: vec![],
+            width: 1,
+            fg,
+            bg,
+            attrs,
+        }
+    }
+
+    pub fn with_width(mut self, width: u8) -> CharacterCell {
+        self.width = width;
+        self
+    }
+
+    pub fn is_wide(&self) -> bool {
+        self.width == 2
+    }
+
+    pub fn is_continuation(&self) -> bool {
+        self.width == 0
+    }
 }
 
 pub struct SimpleTerminalWindow {
@@ -56,423 +82,278 @@ pub struct SimpleTerminalWindow {
     pub width: u16,
     pub height: u16,
     pub title: String,
-    scroll_y: u16,
+    // How many lines the view is scrolled back into the scrollback, 0 shows the live screen
+    scroll_y: usize,
+    last_scrolled_lines: usize,
     grid: EmbedGrid,
     last_mouse_down_pos_coords: (u16, u16),
     last_size: (u16, u16),
     last_pos: (u16, u16),
-    master_fd: File,
-    child_pid: Pid,
-    queue: Arc<SegQueue<String>>,
-    vte_parser: Parser
+    // Set while a button pressed over the content is held and reported to the program
+    reporting_mouse: bool,
+    id: usize,
+    command: Command,
+    // The environment the command runs with, kept to restart it
+    env: Vec<CString>,
+    // None when the command couldn't be started at all
+    child: Option<Child>,
+    wakeup: Sender<()>,
+    pub exit_policy: ExitPolicy,
+    // Whether the program may read the shared clipboard through OSC 52
+    pub allow_clipboard_read: bool,
+    // Set once the command exited, until it gets restarted
+    exit_status: Option<ExitStatus>,
+    closed: bool,
+    vte_parser: Parser,
+    // Set whenever something visible changed since the last frame
+    dirty: bool
 }
 
-impl Perform for EmbedGrid {
-    fn print(&mut self, c: char) {
-        self.printed_chars += 1;
-
-        self.grid[self.cursor.0 + self.cursor.1 * self.width as usize].ch = c;
-        self.grid[self.cursor.0 + self.cursor.1 * self.width as usize].fg = self.fg_color;
-        self.grid[self.cursor.0 + self.cursor.1 * self.width as usize].bg = self.bg_color;
-
-        self.cursor.0 += 1;
+impl SimpleTerminalWindow {
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.vte_parser.advance(&mut self.grid, *byte);
+        }
+        let responses = std::mem::take(&mut self.grid.responses);
+        if !responses.is_empty() {
+            self.write_to_child(&responses);
+        }
+        self.update_title();
     }
 
-    fn execute(&mut self, byte: u8) {
-        match byte {
-            0x08 => {
-                if self.cursor.0 > 0 {
-                    self.cursor.0 -= 1;
-                }
-            },
-            0x0A => {
-                if self.cursor.1 <= self.height {
-                    self.cursor.1 += 1;
-                }
-            }
-            0x0D => {
-                self.cursor.0 = 0;
-            }
-            0x07 => {
-                print!("\x07");
-            }
-            c => {
-                println!("      {:?}", c as char);
-                panic!();
-            }
+    // The title the program set replaces the command, otherwise the directory it reported follows the command
+    fn update_title(&mut self) {
+        let mut title = match (self.grid.title.as_ref().or(self.grid.icon_name.as_ref()), &self.grid.cwd) {
+            (Some(title), _) => format!("{} {}", self.id, title),
+            (None, Some(cwd)) => format!("{} {} ({})", self.id, self.command, cwd.display()),
+            (None, None) => format!("{} {}", self.id, self.command)
         };
+        if let Some(status) = self.exit_status {
+            title.push_str(&format!(" [{}]", status));
+        }
+        self.title = title;
     }
 
-    fn hook(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, action: char) {
-        panic!();
-    }
-
-    fn put(&mut self, byte: u8) {
-        panic!();
-    }
-
-    fn unhook(&mut self) {
-        panic!();
-    }
-
-    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
-        panic!();
-    }
-
-    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, action: char) {
-        return;
-        match action {
-            'A' => { // Cursor Up
-                if (self.cursor.1 as i64) > params[0] {
-                    self.cursor.1 -= params[0] as usize;
-                }
-                else {
-                    self.cursor.1 = 0;
-                }
-            },
-            'B' => { // Cursor Down
-                if (self.height as i64) > self.cursor.1 as i64 + params[0] {
-                    self.cursor.1 += params[0] as usize;
-                }
-                else {
-                    self.cursor.1 = self.height - 1;
-                }
-            },
-            'D' => { // Cursor Back
-                if (self.cursor.0 as i64) > params[0] {
-                    self.cursor.0 -= params[0] as usize;
-                }
-                else {
-                    self.cursor.0 = 0;
-                }
-            },
-            'C' => { // Cursor Forwards
-                if (self.width as i64) > self.cursor.0 as i64 + params[0] {
-                    self.cursor.0 += params[0] as usize;
-                }
-                else {
-                    self.cursor.0 = self.height - 0;
-                }
-            },
-            'E' => { // Go down then to the beginning of the line
-                if (self.height as i64) > self.cursor.1 as i64 + params[0] {
-                    self.cursor.1 += params[0] as usize;
-                }
-                else {
-                    self.cursor.1 = self.height - 1;
-                }
-                self.cursor.0 = 0;
-            },
-            'F' => { // Go up then to the beginning of the line
-                if (self.cursor.1 as i64) > params[0] {
-                    self.cursor.1 -= params[0] as usize;
-                }
-                else {
-                    self.cursor.1 = 0;
-                }
-                self.cursor.0 = 0;
-            },
-            'G' => { // Set cursor horizontal pos
-                if params[0] < self.width as i64 {
-                    if params[0] > 0 {
-                        self.cursor.0 = params[0] as usize;
-                    }
-                    else {
-                        self.cursor.0 = 0;
-                    }
-                }
-                else {
-                    self.cursor.0 = self.width - 1;
-                }
-            },
-            'H' => { // Set cursor pos
-                let y = params[0] - 1;
-                let x = params[1] - 1;
-
-                if x <= self.width as i64 {
-                    if x > 0 {
-                        self.cursor.0 = x as usize;
-                    }
-                    else {
-                        self.cursor.0 = 0;
-                    }
-                }
-                else {
-                    self.cursor.0 = self.width - 1;
-                }
-
-                if y <= self.height as i64 {
-                    if y > 0 {
-                        self.cursor.1 = y as usize;
-                    }
-                    else {
-                        self.cursor.1 = 0;
-                    }
-                }
-                else {
-                    self.cursor.1 = self.height - 1;
+    // Restarts the command, in the directory the previous one last reported if it still exists
+    fn spawn(&mut self) {
+        let mut command = self.command.clone();
+        if let Some(cwd) = self.grid.cwd.take().filter(|cwd| cwd.is_dir()) {
+            command.cwd = Some(cwd);
+        }
+        self.grid.title = None;
+        self.grid.icon_name = None;
+        self.start(&command);
+    }
+
+    // A command that can't be started at all leaves the window held with the reason
+    fn start(&mut self, command: &Command) {
+        match Child::spawn(command, &self.env, self.width, self.height, self.wakeup.clone()) {
+            Ok((child, error)) => {
+                self.child = Some(child);
+                self.exit_status = None;
+                self.update_title();
+                if let Some(error) = error {
+                    self.add_bytes(format!("termui: {}\r\n", error).as_bytes());
                 }
             },
-            'J' => {
-                match params[0] {
-                    0 => {
-                        let index = self.cursor.0 + self.cursor.1 * self.width;
-                        let end = self.width * self.height;
-                        for i in index..end {
-                            self.grid[i] = CharacterCell {
-                                fg: 37,
-                                bg: 40,
-                                ch: ' ',
-                                attrs: Attr::BackgroundColor(40)
-                            }
-                        }
-                    },
-                    1 => {
-                        let index = 0;
-                        let end = self.cursor.0 + self.cursor.1 * self.width + 1;
-                        for i in index..end {
-                            self.grid[i] = CharacterCell {
-                                fg: 37,
-                                bg: 40,
-                                ch: ' ',
-                                attrs: Attr::BackgroundColor(40)
-                            }
-                        }
-                    },
-                    2 | 3 => {
-                        let end = self.cursor.0 + self.cursor.1 * self.width + 1;
-                        for i in 0..end {
-                            self.grid[i] = CharacterCell {
-                                fg: 37,
-                                bg: 40,
-                                ch: ' ',
-                                attrs: Attr::BackgroundColor(40)
-                            }
-                        }
-                    }
-                    _ => {
-
-                    }
-                }
-            }
-            'm' => { // Select Graphic Rendition
-                for i in params {
-                    match i {
-                        30 => { // FG black
-                            self.fg_color = 30;
-                        },
-                        31 => { // FG red
-                            self.fg_color = 31;
-                        },
-                        32 => { // FG green
-                            self.fg_color = 32;
-                        },
-                        33 => { // FG yellow
-                            self.fg_color = 33;
-                        },
-                        34 => { // FG blue
-                            self.fg_color = 34;
-                        },
-                        35 => { // FG magenta
-                            self.fg_color = 35;
-                        },
-                        36 => { // FG cyan
-                            self.fg_color = 36;
-                        },
-                        37 => { // FG white
-                            self.fg_color = 37;
-                        },
-                        39 => { // Default FG color (white)
-                            self.fg_color = 37;
-                        },
-
-                        40 => { // FG black
-                            self.bg_color = 40;
-                        },
-                        41 => { // BG red
-                            self.bg_color = 41;
-                        },
-                        42 => { // BG green
-                            self.bg_color = 42;
-                        },
-                        43 => { // BG yellow
-                            self.bg_color = 43;
-                        },
-                        44 => { // BG blue
-                            self.bg_color = 44;
-                        },
-                        45 => { // BG magenta
-                            self.bg_color = 45;
-                        },
-                        46 => { // BG cyan
-                            self.bg_color = 46;
-                        },
-                        47 => { // BG white
-                            self.bg_color = 47;
-                        },
-                        49 => { // Default BG color (black)
-                            self.bg_color = 40;
-                        }
-
-                        0 => { // Reset all
-                            self.fg_color = 37;
-                            self.bg_color = 40;
-                        }
-                        _ => {
-
-                        }
-                    }
-                }
-            }
-            _ => {
-                panic!();
+            Err(error) => {
+                self.child = None;
+                self.exit_status = Some(ExitStatus::NotStarted);
+                self.update_title();
+                self.add_bytes(format!("termui: {}\r\n[{}, press a key to close]", error, ExitStatus::NotStarted).as_bytes());
             }
         }
     }
 
-    fn esc_dispatch(&mut self, intermediates: &[u8], ignore: bool, byte: u8) {
-        println!("                     {:?}                ", byte);
-        panic!();
+    // Input for a window without a command goes nowhere
+    fn write_to_child(&self, bytes: &[u8]) {
+        if let Some(child) = &self.child {
+            child.write(bytes);
+        }
     }
-}
 
-impl SimpleTerminalWindow {
-    pub fn add_string(&mut self, s: String) {
-        for c in s.bytes() {
-            self.vte_parser.advance(&mut self.grid, c);
+    fn on_child_exit(&mut self, status: ExitStatus) {
+        self.exit_status = Some(status);
+        self.update_title();
+        match self.exit_policy {
+            ExitPolicy::Close => self.closed = true,
+            ExitPolicy::Restart if self.child.as_ref().is_some_and(|child| child.started.elapsed() >= RESTART_MIN_UPTIME) => {
+                // Nothing the dead program set up carries over, not even a sequence it left unfinished
+                self.vte_parser = Parser::new();
+                self.grid.reset_modes();
+                self.add_bytes(format!("\r\n[{}, restarting]\r\n", status).as_bytes());
+                self.spawn();
+            },
+            ExitPolicy::Restart | ExitPolicy::Hold => {
+                self.add_bytes(format!("\r\n[{}, press a key to close]", status).as_bytes());
+            }
         }
     }
 }
 
 pub trait Container {
-    fn update_content(&mut self);
-    fn get_content(&self) -> String;
+    // The clipboard is shared by every container
+    fn update_content(&mut self, clipboard: &mut Clipboard);
+    fn blit(&self, buffer: &mut CellBuffer);
     fn get_x(&self) -> u16;
     fn get_y(&self) -> u16;
     fn get_width(&self) -> u16;
     fn get_height(&self) -> u16;
     fn get_cursor(&self) -> (usize, usize);
     fn get_title(&self) -> Option<&str>;
-    fn input(&mut self, input: String);
     fn set_size(&mut self, width: u16, height: u16);
     fn get_printed_chars(&self) -> usize;
+    fn take_dev_console(&mut self) -> Vec<String>;
+    fn take_dirty(&mut self) -> bool;
+    // Closed containers get removed from the screen
+    fn is_closed(&self) -> bool;
+    // Asks the container to go away, force is used once asking wasn't enough
+    fn shut_down(&mut self, force: bool);
 
     fn on_scroll_y(&mut self, amount: i16);
     fn on_mouse_down(&mut self, x: u16, y: u16);
     fn on_mouse_up(&mut self, x: u16, y: u16);
     fn on_mouse_drag(&mut self, x: u16, y: u16);
+    // Reports the event to the program when it tracks the mouse, returns false when it is left to the window manager
+    fn report_mouse(&mut self, event: MouseEvent) -> bool;
     fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers);
+    fn on_paste(&mut self, text: &str);
 
     fn is_touching(&self, x: u16, y: u16) -> bool;
 }
 
 impl Container for SimpleTerminalWindow {
-    fn update_content(&mut self) {
-        while self.queue.len() > 0 {
-            let pop = self.queue.pop();
-            if pop.is_ok() {
-                self.add_string(pop.unwrap());
+    fn update_content(&mut self, clipboard: &mut Clipboard) {
+        let mut budget = OUTPUT_BUDGET;
+        while budget > 0 {
+            let event = match self.child.as_ref().and_then(|child| child.events.try_recv().ok()) {
+                Some(event) => event,
+                None => break
+            };
+            match event {
+                ChildEvent::Output(bytes) => {
+                    budget = budget.saturating_sub(bytes.len());
+                    self.add_bytes(&bytes);
+                },
+                ChildEvent::Exited(status) => self.on_child_exit(status)
+            }
+            self.dirty = true;
+        }
+        // The rest is handled on the next iteration of the main loop, after input and drawing
+        if self.child.as_ref().is_some_and(|child| !child.events.is_empty()) {
+            let _ = self.wakeup.try_send(());
+        }
+        for request in std::mem::take(&mut self.grid.clipboard_requests) {
+            match request {
+                ClipboardRequest::Set(text) => clipboard.set(text),
+                ClipboardRequest::Query(selection, bell_terminated) if self.allow_clipboard_read => {
+                    let answer = clipboard::osc52(&selection, clipboard.get(), bell_terminated);
+                    self.write_to_child(answer.as_bytes());
+                },
+                ClipboardRequest::Query(..) => self.grid.dev_console.push("Denied a clipboard read".to_string())
             }
         }
+        // Keep a scrolled back view on the same content while new lines come in
+        if self.scroll_y > 0 {
+            self.scroll_y += self.grid.scrolled_lines - self.last_scrolled_lines;
+            self.scroll_y = self.scroll_y.min(self.grid.scrollback_len());
+        }
+        self.last_scrolled_lines = self.grid.scrolled_lines;
     }
 
-    fn get_content(&self) -> String {
-        let mut result = "".to_string();
-        let mut prev_color_fg: u8 = self.grid.grid[0].fg;
-        let mut prev_color_bg: u8 = self.grid.grid[0].bg;
-        for i in 0..self.height {
-            let slice = &self.grid.grid[((i * self.width) as usize)..(((i + 1) * self.width) as usize)];
-            let mut x = 0;
-            for c in slice {
-                let foreground = c.fg;
-                let mut background = c.bg;
-                if x == self.grid.cursor.0 && i as usize == self.grid.cursor.1 {
-                    background = 47;
-                }
-                if foreground != prev_color_fg {
-                    result.push_str(format!("\x1B[{}m", foreground).as_str());
-                    prev_color_fg = foreground;
-                }
-                if background != prev_color_bg {
-                    result.push_str(format!("\x1B[{}m", background).as_str());
-                    prev_color_bg = background;
+    fn blit(&self, buffer: &mut CellBuffer) {
+        for y in 0..self.grid.height {
+            let line = self.grid.view_line(y, self.scroll_y);
+            for (x, cell) in line.iter().enumerate() {
+                let mut cell = cell.clone();
+                if self.grid.cursor_visible && (x, y) == (self.grid.cursor.0, self.grid.cursor.1 + self.scroll_y) {
+                    cell.bg = Color::Ansi(7);
                 }
+                buffer.set(self.x as i32 + x as i32, self.y as i32 + y as i32, cell);
+            }
+        }
 
-                result.push(c.ch);
-                x += 1;
+        // Show where the view is in the scrollback in the top right corner
+        if self.scroll_y > 0 {
+            let indicator = format!("[{}/{}]", self.scroll_y, self.grid.scrollback_len());
+            let x = self.x as i32 + self.width as i32 - indicator.chars().count() as i32;
+            for (i, ch) in indicator.chars().enumerate() {
+                buffer.set(x + i as i32, self.y as i32, CharacterCell::new(ch, Color::Default, Color::Default, Attributes::REVERSE));
             }
-            result.push('\n');
         }
-        return result;
     }
 
     fn get_x(&self) -> u16 {
-        return self.x;
+        self.x
     }
 
     fn get_y(&self) -> u16 {
-        return self.y;
+        self.y
     }
 
     fn get_width(&self) -> u16 {
-        return self.width;
+        self.width
     }
 
     fn get_height(&self) -> u16 {
-        return self.height;
+        self.height
     }
 
     fn get_cursor(&self) -> (usize, usize) {
-        return self.grid.cursor;
+        self.grid.cursor
     }
 
     fn get_title(&self) -> Option<&str> {
-        return Some(&self.title);
-    }
-
-    fn input(&mut self, input: String) {
-        self.add_string(input);
+        Some(&self.title)
     }
 
     fn set_size(&mut self, width: u16, height: u16) {
+        self.dirty = true;
         self.width = width;
         self.height = height;
-        self.grid.width = width as usize;
-        self.grid.height = height as usize;
-        self.grid.cursor = (0, 0);
-
-        self.grid.grid = vec![CharacterCell {
-            attrs: Attr::BackgroundColor(40),
-            ch: ' ',
-            bg: 40,
-            fg: 37,
-        }; width as usize * height as usize];
-        let winsize = Winsize {
-            ws_row: self.height,
-            ws_col: self.width,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        };
-        let master_fd = self.master_fd.as_raw_fd();
-
-        unsafe { set_window_size(master_fd, &winsize).unwrap() };
-        nix::sys::signal::kill(self.child_pid, nix::sys::signal::SIGWINCH).unwrap();
+        self.grid.resize(width as usize, height as usize);
+        self.scroll_y = 0;
+        if let Some(child) = &self.child {
+            child.resize(width, height);
+        }
     }
 
     fn get_printed_chars(&self) -> usize {
-        return self.grid.printed_chars;
+        self.grid.printed_chars
+    }
+
+    fn take_dev_console(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.grid.dev_console)
+    }
+
+    fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn shut_down(&mut self, force: bool) {
+        if self.exit_status.is_some() {
+            self.closed = true;
+            return;
+        }
+        // The window closes once the waiter thread reaped the child
+        self.exit_policy = ExitPolicy::Close;
+        if let Some(child) = &self.child {
+            child.signal(if force { Signal::SIGKILL } else { Signal::SIGHUP });
+        }
     }
 
     fn on_scroll_y(&mut self, amount: i16) {
-        if amount < 0 {
-            if (-amount) as u16 > self.scroll_y {
-                self.scroll_y = 0;
-            } else {
-                self.scroll_y -= (-amount) as u16;
-            }
-        } else {
-            self.scroll_y += amount as u16;
+        if self.grid.is_alternate_screen() {
+            return;
         }
+        self.dirty = true;
+        // Negative amounts scroll up, further back into the scrollback
+        let scroll_y = self.scroll_y as i64 - amount as i64;
+        self.scroll_y = scroll_y.max(0).min(self.grid.scrollback_len() as i64) as usize;
     }
 
     fn on_mouse_down(&mut self, x: u16, y: u16) {
@@ -481,25 +362,24 @@ impl Container for SimpleTerminalWindow {
         self.last_pos = (self.x, self.y);
     }
 
-    fn on_mouse_up(&mut self, x: u16, y: u16) {
+    fn on_mouse_up(&mut self, _x: u16, _y: u16) {
         self.last_size = (self.width, self.height);
         self.last_pos = (self.x, self.y);
     }
 
     fn on_mouse_drag(&mut self, x: u16, y: u16) {
+        self.dirty = true;
         if self.last_mouse_down_pos_coords.0 == self.x + self.last_size.0 &&
             self.last_mouse_down_pos_coords.1 >= self.last_pos.1 &&
-            self.last_mouse_down_pos_coords.1 < self.last_pos.1 + self.last_size.1 + 1 {
-            if x > self.x {
-                self.set_size(x - self.x, self.height);
-            }
+            self.last_mouse_down_pos_coords.1 < self.last_pos.1 + self.last_size.1 + 1 &&
+            x > self.x {
+            self.set_size(x - self.x, self.height);
         }
         if self.last_mouse_down_pos_coords.1 == self.y + self.last_size.1 &&
             self.last_mouse_down_pos_coords.0 >= self.last_pos.0 &&
-            self.last_mouse_down_pos_coords.0 < self.last_pos.0 + self.last_size.0 + 1 {
-            if y > self.y {
-                self.set_size(self.width, y - self.y);
-            }
+            self.last_mouse_down_pos_coords.0 < self.last_pos.0 + self.last_size.0 + 1 &&
+            y > self.y {
+            self.set_size(self.width, y - self.y);
         }
 
         if self.last_mouse_down_pos_coords.1 == self.last_pos.1 - 1 &&
@@ -515,172 +395,161 @@ impl Container for SimpleTerminalWindow {
         }
     }
 
-    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
-        match code {
-            KeyCode::Char(c) => {
-                self.master_fd.write(&[c as u8]).unwrap();
-            }
-            KeyCode::Enter => {
-                self.master_fd.write(&['\n' as u8]).unwrap();
-            }
-            KeyCode::Backspace => {
-                self.master_fd.write((&[0x08 as u8])).unwrap();
-            }
-            KeyCode::Left => {
-                self.master_fd.write_all(&[0x1B as u8, '[' as u8, 'D' as u8]);
-            }
-            KeyCode::Right => {
-                self.master_fd.write_all(&[0x1B as u8, '[' as u8, 'C' as u8]);
-            }
-            KeyCode::Up => {
-                self.master_fd.write_all(&[0x1B as u8, '[' as u8, 'A' as u8]);
-            }
-            KeyCode::Down => {
-                self.master_fd.write_all(&[0x1B as u8, '[' as u8, 'B' as u8]);
-            }
+    fn report_mouse(&mut self, event: MouseEvent) -> bool {
+        let (x, y) = (event.column, event.row);
+        // Drags and releases belong to whoever got the press, even once they leave the content
+        let reported = match event.kind {
+            MouseEventKind::Drag(_) | MouseEventKind::Up(_) => self.reporting_mouse,
+            _ => self.exit_status.is_none() && self.grid.mouse_tracking != MouseTracking::Off &&
+                x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+        };
+        match event.kind {
+            MouseEventKind::Down(_) => self.reporting_mouse = reported,
+            MouseEventKind::Up(_) => self.reporting_mouse = false,
             _ => {}
         }
+        if !reported {
+            return false;
+        }
+        let column = x.saturating_sub(self.x).min(self.width - 1);
+        let row = y.saturating_sub(self.y).min(self.height - 1);
+        let bytes = mouse::encode_mouse(event, column, row, self.grid.mouse_tracking, self.grid.mouse_encoding);
+        self.write_to_child(&bytes);
+        true
+    }
+
+    fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        // A held window is dismissed by any key
+        if self.exit_status.is_some() {
+            self.closed = true;
+            return;
+        }
+        // Typing jumps back to the live screen
+        if self.scroll_y > 0 {
+            self.scroll_y = 0;
+            self.dirty = true;
+        }
+        let bytes = keys::encode_key(code, modifiers, self.grid.application_cursor_keys);
+        // The child may already be gone, its exit comes in through its events
+        self.write_to_child(&bytes);
+    }
+
+    fn on_paste(&mut self, text: &str) {
+        if self.exit_status.is_some() {
+            return;
+        }
+        if self.scroll_y > 0 {
+            self.scroll_y = 0;
+            self.dirty = true;
+        }
+        self.write_to_child(&keys::encode_paste(text, self.grid.bracketed_paste));
     }
 
     fn is_touching(&self, x: u16, y: u16) -> bool {
-        return x >= self.x - 1 && x <= self.x + self.width &&
-            y >= self.y - 1 && y <= self.y + self.height;
+        x >= self.x - 1 && x <= self.x + self.width &&
+            y >= self.y - 1 && y <= self.y + self.height
     }
 }
 
 impl SimpleTerminalWindow {
-    pub fn new(x: u16, y: u16, width: u16, height: u16, title: String) -> SimpleTerminalWindow {
-        let lines: Vec<String> = vec!["".to_string()];
-
-        let queue: Arc<SegQueue<String>> = Arc::new(SegQueue::new());
-        let q = queue.clone();
-
-        let master_fd = posix_openpt(OFlag::O_RDWR).unwrap();
-        grantpt(&master_fd).unwrap();
-        unlockpt(&master_fd).unwrap();
-        let slave_name = unsafe { ptsname(&master_fd) }.unwrap();
-        let slave_fd = open(Path::new(&slave_name), OFlag::O_RDWR, Mode::empty()).unwrap();
-        let mut m: File = unsafe { std::fs::File::from_raw_fd(master_fd.as_raw_fd()) };
-        let child_pid;
-
-        child_pid = match fork() {
-            Ok(ForkResult::Parent { child, .. }) => {
-                child
-            }
-            Ok(ForkResult::Child) => {
-                setsid().unwrap();
-                unsafe {
-                    if libc::ioctl(slave_fd, TIOCSCTTY) == -1 {
-                        println!("ERROR ioctl() {}", errno::errno());
-                    }
-                }
-                dup2(slave_fd, 0); // stdin
-                dup2(slave_fd, 1); // stdout
-                dup2(slave_fd, 2); // stderr
-                unsafe {
-                    let shell = CString::new("/bin/bash").unwrap();
-                    let arg0 = shell.clone();
-                    let term = CString::new("TERM=dumb").unwrap();
-                    let env = [term.as_ptr(), ptr::null()];
-
-                    if libc::execle(shell.as_ptr(), arg0.as_ptr(), ptr::null() as *const c_void, env.as_ptr()) == -1 {
-                        println!("ERROR execle() ({})", errno::errno());
-                    }
-
-                    exit(-1);
-                }
-            }
-            Err(e) => panic!(e),
-        };
-
-        std::thread::Builder::new()
-            .spawn(move || {
-                let winsize = Winsize {
-                    ws_row: height,
-                    ws_col: width,
-                    ws_xpixel: 0,
-                    ws_ypixel: 0,
-                };
-                let master_fd = master_fd.as_raw_fd();
-                unsafe { set_window_size(master_fd, &winsize).unwrap() };
-                let mut master_file: File = unsafe { std::fs::File::from_raw_fd(master_fd) };
-                fn liaison(mut pty_fd: std::fs::File, q: Arc<SegQueue<String>>) {
-                    loop {
-                        let mut buf = [0; 1024];
-                        let r = pty_fd.read(&mut buf);
-                        let n = r.unwrap();
-                        let str = str::from_utf8(&buf[..n]).unwrap().to_string();
-                        q.push(str);
-                    }
-                }
-
-                liaison(master_file, q);
-            })
-            .unwrap();
-
-        return SimpleTerminalWindow {
+    pub fn new(x: u16, y: u16, width: u16, height: u16, command: &Command, config: &Config, wakeup: Sender<()>) -> SimpleTerminalWindow {
+        let id = NEXT_WINDOW_ID.fetch_add(1, Ordering::Relaxed);
+        let env = child::child_environment(config, command, id);
+        let mut window = SimpleTerminalWindow {
             x,
             y,
             width,
             height,
-            title,
+            title: String::new(),
             scroll_y: 0,
-            grid: EmbedGrid {
-                cursor: (0, 0),
-                width: width as usize,
-                height: height as usize,
-                bg_color: 0,
-                fg_color: 15,
-                grid: vec![CharacterCell {
-                    attrs: Attr::BackgroundColor(0),
-                    ch: ' ',
-                    bg: 40,
-                    fg: 37,
-                }; width as usize * height as usize],
-                printed_chars: 0
-            },
+            last_scrolled_lines: 0,
+            grid: EmbedGrid::new(width as usize, height as usize, config.scrollback_lines),
             last_mouse_down_pos_coords: (0, 0),
             last_size: (width, height),
             last_pos: (x, y),
-            master_fd: m,
-            child_pid,
-            queue,
-            vte_parser: Parser::new()
+            reporting_mouse: false,
+            id,
+            command: command.clone(),
+            env,
+            child: None,
+            wakeup,
+            exit_policy: config.exit_policy,
+            allow_clipboard_read: config.clipboard_read.allows(id),
+            exit_status: None,
+            closed: false,
+            vte_parser: Parser::new(),
+            dirty: true
         };
+        window.start(command);
+        window
     }
 }
 
+const DEV_CONSOLE_MAX_LINES: usize = 256;
+
 pub struct Screen {
     pub containers: Vec<Rc<RefCell<Box<dyn Container>>>>,
-    pub dev_console: Vec<String>
+    pub clipboard: Clipboard,
+    pub dev_console: Vec<String>,
+    // Set for changes that aren't tied to a single container, like the stacking order
+    dirty: bool
 }
 
 impl Screen {
     pub fn new() -> Screen {
-        return Screen {
+        Screen {
             containers: vec![],
-            dev_console: vec![]
-        };
+            clipboard: Clipboard::default(),
+            dev_console: vec![],
+            dirty: true
+        }
     }
 
-    pub fn add_container(&mut self, con: Rc<RefCell<Box<dyn Container>>>) {
-        self.containers.push(con);
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
     }
 
-    pub fn get_container(&self, index: u16) -> Option<Rc<RefCell<Box<dyn Container>>>> {
-        let con = self.containers.get(index as usize);
-        if con.is_none() {
-            return None;
+    // Tells whether a new frame has to be drawn, and resets every dirty flag
+    pub fn take_dirty(&mut self) -> bool {
+        let mut dirty = std::mem::replace(&mut self.dirty, false);
+        for con in self.containers.iter() {
+            dirty |= con.deref().borrow_mut().take_dirty();
         }
-        return Some(con.unwrap().clone());
+        dirty
     }
 
-    pub fn get_top_container(&self) -> Option<Rc<RefCell<Box<dyn Container>>>> {
-        let con = self.containers.last();
-        if con.is_none() {
-            return None;
+    pub fn log(&mut self, message: String) {
+        self.dirty = true;
+        self.dev_console.push(message);
+        if self.dev_console.len() > DEV_CONSOLE_MAX_LINES {
+            self.dev_console.remove(0);
         }
-        return Some(con.unwrap().clone());
+    }
+
+    pub fn update_content(&mut self) {
+        let mut messages = vec![];
+        for con in self.containers.iter() {
+            let mut con = con.deref().borrow_mut();
+            con.update_content(&mut self.clipboard);
+            messages.append(&mut con.take_dev_console());
+        }
+        for message in messages {
+            self.log(message);
+        }
+        let containers_len = self.containers.len();
+        self.containers.retain(|con| !con.deref().borrow().is_closed());
+        if self.containers.len() != containers_len {
+            self.dirty = true;
+        }
+    }
+
+    pub fn add_container(&mut self, con: Rc<RefCell<Box<dyn Container>>>) {
+        self.dirty = true;
+        self.containers.push(con);
+    }
+
+    pub fn get_top_container(&self) -> Option<Rc<RefCell<Box<dyn Container>>>> {
+        self.containers.last().cloned()
     }
 
     pub fn check_top_container(&mut self, x: u16, y: u16) {
@@ -692,6 +561,7 @@ impl Screen {
                     con = self.containers.remove(i);
                 }
                 self.containers.push(con);
+                self.dirty = true;
                 return;
             }
         }
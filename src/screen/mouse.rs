@@ -0,0 +1,151 @@
+use crossterm::event::{KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+
+// Which mouse events the program asked for, set through DECSET 9, 1000, 1002 and 1003
+#[derive(Copy, Clone, PartialEq)]
+pub enum MouseTracking {
+    Off,
+    // X10 compatibility, presses only and without modifiers
+    Press,
+    // Presses and releases
+    Click,
+    // Also motion while a button is held
+    ButtonMotion,
+    // Also motion without a button
+    AnyMotion,
+}
+
+// How reports are written, set through DECSET 1006 and 1015
+#[derive(Copy, Clone, PartialEq)]
+pub enum MouseEncoding {
+    // CSI M followed by three bytes, which can't express coordinates past 223
+    Default,
+    Sgr,
+    Urxvt,
+}
+
+fn button_code(button: MouseButton) -> u8 {
+    match button {
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+    }
+}
+
+fn modifier_code(modifiers: KeyModifiers) -> u8 {
+    let mut code = 0;
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        code += 4;
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        code += 8;
+    }
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        code += 16;
+    }
+    code
+}
+
+// Encodes an event at the given column and row of the window's content, both starting at 0.
+// An empty result means the event isn't reported in this tracking mode
+pub fn encode_mouse(event: MouseEvent, column: u16, row: u16, tracking: MouseTracking, encoding: MouseEncoding) -> Vec<u8> {
+    let (code, released) = match event.kind {
+        MouseEventKind::Down(button) => (button_code(button), false),
+        MouseEventKind::Up(button) => {
+            if tracking == MouseTracking::Press {
+                return vec![];
+            }
+            // Only SGR tells which button was released
+            let code = if encoding == MouseEncoding::Sgr { button_code(button) } else { 3 };
+            (code, true)
+        },
+        MouseEventKind::Drag(button) => {
+            if tracking != MouseTracking::ButtonMotion && tracking != MouseTracking::AnyMotion {
+                return vec![];
+            }
+            (button_code(button) + 32, false)
+        },
+        MouseEventKind::Moved => {
+            if tracking != MouseTracking::AnyMotion {
+                return vec![];
+            }
+            // Motion without a button is reported as a release
+            (3 + 32, false)
+        },
+        MouseEventKind::ScrollUp => (64, false),
+        MouseEventKind::ScrollDown => (65, false),
+    };
+    let code = match tracking {
+        MouseTracking::Off => return vec![],
+        MouseTracking::Press => code,
+        _ => code + modifier_code(event.modifiers),
+    };
+    let (x, y) = (column as u32 + 1, row as u32 + 1);
+    match encoding {
+        MouseEncoding::Default => {
+            if x > 223 || y > 223 {
+                return vec![];
+            }
+            vec![0x1B, b'[', b'M', 32 + code, 32 + x as u8, 32 + y as u8]
+        },
+        MouseEncoding::Sgr => format!("\x1B[<{};{};{}{}", code, x, y, if released { 'm' } else { 'M' }).into_bytes(),
+        MouseEncoding::Urxvt => format!("\x1B[{};{};{}M", 32 + code as u32, x, y).into_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::{KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+    use super::{encode_mouse, MouseEncoding, MouseTracking};
+
+    fn event(kind: MouseEventKind, modifiers: KeyModifiers) -> MouseEvent {
+        // The position is passed separately, relative to the window
+        MouseEvent {
+            kind,
+            column: 0,
+            row: 0,
+            modifiers,
+        }
+    }
+
+    #[test]
+    fn sgr_reports_tell_the_released_button() {
+        let down = event(MouseEventKind::Down(MouseButton::Right), KeyModifiers::NONE);
+        let up = event(MouseEventKind::Up(MouseButton::Right), KeyModifiers::NONE);
+        assert_eq!(encode_mouse(down, 4, 9, MouseTracking::Click, MouseEncoding::Sgr), b"\x1B[<2;5;10M");
+        assert_eq!(encode_mouse(up, 4, 9, MouseTracking::Click, MouseEncoding::Sgr), b"\x1B[<2;5;10m");
+        assert_eq!(encode_mouse(up, 4, 9, MouseTracking::Click, MouseEncoding::Default), b"\x1B[M#%*");
+    }
+
+    #[test]
+    fn modifiers_and_wheel() {
+        let down = event(MouseEventKind::Down(MouseButton::Left), KeyModifiers::SHIFT | KeyModifiers::CONTROL);
+        assert_eq!(encode_mouse(down, 0, 0, MouseTracking::Click, MouseEncoding::Sgr), b"\x1B[<20;1;1M");
+        // X10 compatibility mode leaves the modifiers out
+        assert_eq!(encode_mouse(down, 0, 0, MouseTracking::Press, MouseEncoding::Sgr), b"\x1B[<0;1;1M");
+        let scroll = event(MouseEventKind::ScrollDown, KeyModifiers::NONE);
+        assert_eq!(encode_mouse(scroll, 0, 0, MouseTracking::Click, MouseEncoding::Urxvt), b"\x1B[97;1;1M");
+    }
+
+    #[test]
+    fn tracking_modes_filter_events() {
+        let up = event(MouseEventKind::Up(MouseButton::Left), KeyModifiers::NONE);
+        let drag = event(MouseEventKind::Drag(MouseButton::Left), KeyModifiers::NONE);
+        let moved = event(MouseEventKind::Moved, KeyModifiers::NONE);
+        assert!(encode_mouse(up, 0, 0, MouseTracking::Off, MouseEncoding::Sgr).is_empty());
+        assert!(encode_mouse(up, 0, 0, MouseTracking::Press, MouseEncoding::Sgr).is_empty());
+        assert!(encode_mouse(drag, 0, 0, MouseTracking::Click, MouseEncoding::Sgr).is_empty());
+        assert_eq!(encode_mouse(drag, 0, 0, MouseTracking::ButtonMotion, MouseEncoding::Sgr), b"\x1B[<32;1;1M");
+        assert!(encode_mouse(moved, 0, 0, MouseTracking::ButtonMotion, MouseEncoding::Sgr).is_empty());
+        assert_eq!(encode_mouse(moved, 0, 0, MouseTracking::AnyMotion, MouseEncoding::Sgr), b"\x1B[<35;1;1M");
+    }
+
+    #[test]
+    fn coordinates_past_223() {
+        let down = event(MouseEventKind::Down(MouseButton::Left), KeyModifiers::NONE);
+        assert_eq!(encode_mouse(down, 222, 0, MouseTracking::Click, MouseEncoding::Default), [0x1B, b'[', b'M', 32, 255, 33]);
+        // The default encoding can't express them, the others can
+        assert!(encode_mouse(down, 223, 0, MouseTracking::Click, MouseEncoding::Default).is_empty());
+        assert_eq!(encode_mouse(down, 299, 0, MouseTracking::Click, MouseEncoding::Sgr), b"\x1B[<0;300;1M");
+        assert_eq!(encode_mouse(down, 299, 0, MouseTracking::Click, MouseEncoding::Urxvt), b"\x1B[32;300;1M");
+    }
+}